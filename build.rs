@@ -0,0 +1,13 @@
+use std::{env, path::Path};
+
+// Writes the linker map next to the image, e.g.
+// target/x86_64-unknown-uefi/debug/yboot2.map, so it follows
+// CARGO_TARGET_DIR and the profile. tools/embed-symbols.py reads it to
+// fill in the symbol table for backtraces, see the Makefile
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    // OUT_DIR is <profile dir>/build/yboot2-<hash>/out
+    let profile_dir = Path::new(&out_dir).ancestors().nth(3).unwrap();
+
+    println!("cargo:rustc-link-arg-bins=/map:{}", profile_dir.join("yboot2.map").display());
+}
@@ -6,13 +6,57 @@ pub type Event = *mut c_void;
 
 pub type Result<T> = core::result::Result<T, Status>;
 
+// Every status code defined by the UEFI specification. Warnings (the high
+// bit clear, non-zero) report an operation which did complete, so they
+// don't turn into errors when converted to a Result
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum Status {
     Success,
-    Err,
+
+    WarnUnknownGlyph,
+    WarnDeleteFailure,
+    WarnWriteFailure,
+    WarnBufferTooSmall,
+    WarnStaleData,
+    WarnFileSystem,
+    WarnResetRequired,
+
+    LoadError,
     InvalidParameter,
+    Unsupported,
+    BadBufferSize,
     BufferTooSmall,
-    NotReady
+    NotReady,
+    DeviceError,
+    WriteProtected,
+    OutOfResources,
+    VolumeCorrupted,
+    VolumeFull,
+    NoMedia,
+    MediaChanged,
+    NotFound,
+    AccessDenied,
+    NoResponse,
+    NoMapping,
+    Timeout,
+    NotStarted,
+    AlreadyStarted,
+    Aborted,
+    IcmpError,
+    TftpError,
+    ProtocolError,
+    IncompatibleVersion,
+    SecurityViolation,
+    CrcError,
+    EndOfMedia,
+    EndOfFile,
+    InvalidLanguage,
+    CompromisedData,
+    IpAddressConflict,
+    HttpError,
+
+    // Codes not listed above (OEM or newer revisions), kept as returned
+    Unknown(u64)
 }
 
 pub trait Termination {
@@ -21,18 +65,104 @@ pub trait Termination {
 
 const EFI_SUCCESS:                  u64 = 0;
 const EFI_ERR:                      u64 = 0x8000000000000000;
+
+const EFI_WARN_UNKNOWN_GLYPH:       u64 = 1;
+const EFI_WARN_DELETE_FAILURE:      u64 = 2;
+const EFI_WARN_WRITE_FAILURE:       u64 = 3;
+const EFI_WARN_BUFFER_TOO_SMALL:    u64 = 4;
+const EFI_WARN_STALE_DATA:          u64 = 5;
+const EFI_WARN_FILE_SYSTEM:         u64 = 6;
+const EFI_WARN_RESET_REQUIRED:      u64 = 7;
+
+const EFI_LOAD_ERROR:               u64 = EFI_ERR | 0x01;
 const EFI_INVALID_PARAMETER:        u64 = EFI_ERR | 0x02;
+const EFI_UNSUPPORTED:              u64 = EFI_ERR | 0x03;
+const EFI_BAD_BUFFER_SIZE:          u64 = EFI_ERR | 0x04;
 const EFI_BUFFER_TOO_SMALL:         u64 = EFI_ERR | 0x05;
 const EFI_NOT_READY:                u64 = EFI_ERR | 0x06;
+const EFI_DEVICE_ERROR:             u64 = EFI_ERR | 0x07;
+const EFI_WRITE_PROTECTED:          u64 = EFI_ERR | 0x08;
+const EFI_OUT_OF_RESOURCES:         u64 = EFI_ERR | 0x09;
+const EFI_VOLUME_CORRUPTED:         u64 = EFI_ERR | 0x0A;
+const EFI_VOLUME_FULL:              u64 = EFI_ERR | 0x0B;
+const EFI_NO_MEDIA:                 u64 = EFI_ERR | 0x0C;
+const EFI_MEDIA_CHANGED:            u64 = EFI_ERR | 0x0D;
+const EFI_NOT_FOUND:                u64 = EFI_ERR | 0x0E;
+const EFI_ACCESS_DENIED:            u64 = EFI_ERR | 0x0F;
+const EFI_NO_RESPONSE:              u64 = EFI_ERR | 0x10;
+const EFI_NO_MAPPING:               u64 = EFI_ERR | 0x11;
+const EFI_TIMEOUT:                  u64 = EFI_ERR | 0x12;
+const EFI_NOT_STARTED:              u64 = EFI_ERR | 0x13;
+const EFI_ALREADY_STARTED:          u64 = EFI_ERR | 0x14;
+const EFI_ABORTED:                  u64 = EFI_ERR | 0x15;
+const EFI_ICMP_ERROR:               u64 = EFI_ERR | 0x16;
+const EFI_TFTP_ERROR:               u64 = EFI_ERR | 0x17;
+const EFI_PROTOCOL_ERROR:           u64 = EFI_ERR | 0x18;
+const EFI_INCOMPATIBLE_VERSION:     u64 = EFI_ERR | 0x19;
+const EFI_SECURITY_VIOLATION:       u64 = EFI_ERR | 0x1A;
+const EFI_CRC_ERROR:                u64 = EFI_ERR | 0x1B;
+const EFI_END_OF_MEDIA:             u64 = EFI_ERR | 0x1C;
+const EFI_END_OF_FILE:              u64 = EFI_ERR | 0x1F;
+const EFI_INVALID_LANGUAGE:         u64 = EFI_ERR | 0x20;
+const EFI_COMPROMISED_DATA:         u64 = EFI_ERR | 0x21;
+const EFI_IP_ADDRESS_CONFLICT:      u64 = EFI_ERR | 0x22;
+const EFI_HTTP_ERROR:               u64 = EFI_ERR | 0x23;
+
+impl Status {
+    pub fn is_error(self) -> bool {
+        return u64::from(self) & EFI_ERR != 0;
+    }
+
+    pub fn is_warning(self) -> bool {
+        return self != Status::Success && !self.is_error();
+    }
+}
 
 impl From<Status> for u64 {
     fn from(s: Status) -> u64 {
         match s {
             Status::Success             => EFI_SUCCESS,
-            Status::Err                 => EFI_ERR,
+            Status::WarnUnknownGlyph    => EFI_WARN_UNKNOWN_GLYPH,
+            Status::WarnDeleteFailure   => EFI_WARN_DELETE_FAILURE,
+            Status::WarnWriteFailure    => EFI_WARN_WRITE_FAILURE,
+            Status::WarnBufferTooSmall  => EFI_WARN_BUFFER_TOO_SMALL,
+            Status::WarnStaleData       => EFI_WARN_STALE_DATA,
+            Status::WarnFileSystem      => EFI_WARN_FILE_SYSTEM,
+            Status::WarnResetRequired   => EFI_WARN_RESET_REQUIRED,
+            Status::LoadError           => EFI_LOAD_ERROR,
             Status::InvalidParameter    => EFI_INVALID_PARAMETER,
+            Status::Unsupported         => EFI_UNSUPPORTED,
+            Status::BadBufferSize       => EFI_BAD_BUFFER_SIZE,
             Status::BufferTooSmall      => EFI_BUFFER_TOO_SMALL,
             Status::NotReady            => EFI_NOT_READY,
+            Status::DeviceError         => EFI_DEVICE_ERROR,
+            Status::WriteProtected      => EFI_WRITE_PROTECTED,
+            Status::OutOfResources      => EFI_OUT_OF_RESOURCES,
+            Status::VolumeCorrupted     => EFI_VOLUME_CORRUPTED,
+            Status::VolumeFull          => EFI_VOLUME_FULL,
+            Status::NoMedia             => EFI_NO_MEDIA,
+            Status::MediaChanged        => EFI_MEDIA_CHANGED,
+            Status::NotFound            => EFI_NOT_FOUND,
+            Status::AccessDenied        => EFI_ACCESS_DENIED,
+            Status::NoResponse          => EFI_NO_RESPONSE,
+            Status::NoMapping           => EFI_NO_MAPPING,
+            Status::Timeout             => EFI_TIMEOUT,
+            Status::NotStarted          => EFI_NOT_STARTED,
+            Status::AlreadyStarted      => EFI_ALREADY_STARTED,
+            Status::Aborted             => EFI_ABORTED,
+            Status::IcmpError           => EFI_ICMP_ERROR,
+            Status::TftpError           => EFI_TFTP_ERROR,
+            Status::ProtocolError       => EFI_PROTOCOL_ERROR,
+            Status::IncompatibleVersion => EFI_INCOMPATIBLE_VERSION,
+            Status::SecurityViolation   => EFI_SECURITY_VIOLATION,
+            Status::CrcError            => EFI_CRC_ERROR,
+            Status::EndOfMedia          => EFI_END_OF_MEDIA,
+            Status::EndOfFile           => EFI_END_OF_FILE,
+            Status::InvalidLanguage     => EFI_INVALID_LANGUAGE,
+            Status::CompromisedData     => EFI_COMPROMISED_DATA,
+            Status::IpAddressConflict   => EFI_IP_ADDRESS_CONFLICT,
+            Status::HttpError           => EFI_HTTP_ERROR,
+            Status::Unknown(code)       => code,
         }
     }
 }
@@ -40,21 +170,107 @@ impl From<Status> for u64 {
 impl From<u64> for Status {
     fn from(s: u64) -> Self {
         match s {
-            0                       => Status::Success,
-            EFI_ERR                 => Status::Err,
-            EFI_INVALID_PARAMETER   => Status::InvalidParameter,
-            EFI_BUFFER_TOO_SMALL    => Status::BufferTooSmall,
-            EFI_NOT_READY           => Status::NotReady,
-            _                       => Status::Err
+            EFI_SUCCESS                 => Status::Success,
+            EFI_WARN_UNKNOWN_GLYPH      => Status::WarnUnknownGlyph,
+            EFI_WARN_DELETE_FAILURE     => Status::WarnDeleteFailure,
+            EFI_WARN_WRITE_FAILURE      => Status::WarnWriteFailure,
+            EFI_WARN_BUFFER_TOO_SMALL   => Status::WarnBufferTooSmall,
+            EFI_WARN_STALE_DATA         => Status::WarnStaleData,
+            EFI_WARN_FILE_SYSTEM        => Status::WarnFileSystem,
+            EFI_WARN_RESET_REQUIRED     => Status::WarnResetRequired,
+            EFI_LOAD_ERROR              => Status::LoadError,
+            EFI_INVALID_PARAMETER       => Status::InvalidParameter,
+            EFI_UNSUPPORTED             => Status::Unsupported,
+            EFI_BAD_BUFFER_SIZE         => Status::BadBufferSize,
+            EFI_BUFFER_TOO_SMALL        => Status::BufferTooSmall,
+            EFI_NOT_READY               => Status::NotReady,
+            EFI_DEVICE_ERROR            => Status::DeviceError,
+            EFI_WRITE_PROTECTED         => Status::WriteProtected,
+            EFI_OUT_OF_RESOURCES        => Status::OutOfResources,
+            EFI_VOLUME_CORRUPTED        => Status::VolumeCorrupted,
+            EFI_VOLUME_FULL             => Status::VolumeFull,
+            EFI_NO_MEDIA                => Status::NoMedia,
+            EFI_MEDIA_CHANGED           => Status::MediaChanged,
+            EFI_NOT_FOUND               => Status::NotFound,
+            EFI_ACCESS_DENIED           => Status::AccessDenied,
+            EFI_NO_RESPONSE             => Status::NoResponse,
+            EFI_NO_MAPPING              => Status::NoMapping,
+            EFI_TIMEOUT                 => Status::Timeout,
+            EFI_NOT_STARTED             => Status::NotStarted,
+            EFI_ALREADY_STARTED         => Status::AlreadyStarted,
+            EFI_ABORTED                 => Status::Aborted,
+            EFI_ICMP_ERROR              => Status::IcmpError,
+            EFI_TFTP_ERROR              => Status::TftpError,
+            EFI_PROTOCOL_ERROR          => Status::ProtocolError,
+            EFI_INCOMPATIBLE_VERSION    => Status::IncompatibleVersion,
+            EFI_SECURITY_VIOLATION      => Status::SecurityViolation,
+            EFI_CRC_ERROR               => Status::CrcError,
+            EFI_END_OF_MEDIA            => Status::EndOfMedia,
+            EFI_END_OF_FILE             => Status::EndOfFile,
+            EFI_INVALID_LANGUAGE        => Status::InvalidLanguage,
+            EFI_COMPROMISED_DATA        => Status::CompromisedData,
+            EFI_IP_ADDRESS_CONFLICT     => Status::IpAddressConflict,
+            EFI_HTTP_ERROR              => Status::HttpError,
+            code                        => Status::Unknown(code)
         }
     }
 }
 
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match *self {
+            Status::Success             => "success",
+            Status::WarnUnknownGlyph    => "unknown glyph (warning)",
+            Status::WarnDeleteFailure   => "file handle closed, but not deleted (warning)",
+            Status::WarnWriteFailure    => "file handle closed, but data not flushed (warning)",
+            Status::WarnBufferTooSmall  => "buffer too small, data truncated (warning)",
+            Status::WarnStaleData       => "data not updated within the expected time (warning)",
+            Status::WarnFileSystem      => "buffer contains a file system (warning)",
+            Status::WarnResetRequired   => "system reset required (warning)",
+            Status::LoadError           => "image failed to load",
+            Status::InvalidParameter    => "invalid parameter",
+            Status::Unsupported         => "operation not supported",
+            Status::BadBufferSize       => "bad buffer size",
+            Status::BufferTooSmall      => "buffer too small",
+            Status::NotReady            => "no data pending",
+            Status::DeviceError         => "device error",
+            Status::WriteProtected      => "device is write protected",
+            Status::OutOfResources      => "out of resources",
+            Status::VolumeCorrupted     => "file system is corrupted",
+            Status::VolumeFull          => "no space left on the file system",
+            Status::NoMedia             => "no medium in the device",
+            Status::MediaChanged        => "medium has changed",
+            Status::NotFound            => "item not found",
+            Status::AccessDenied        => "access denied",
+            Status::NoResponse          => "server not found or did not respond",
+            Status::NoMapping           => "no mapping for the device",
+            Status::Timeout             => "timeout expired",
+            Status::NotStarted          => "protocol not started",
+            Status::AlreadyStarted      => "protocol already started",
+            Status::Aborted             => "operation aborted",
+            Status::IcmpError           => "ICMP error",
+            Status::TftpError           => "TFTP error",
+            Status::ProtocolError       => "network protocol error",
+            Status::IncompatibleVersion => "incompatible version",
+            Status::SecurityViolation   => "security violation",
+            Status::CrcError            => "CRC error",
+            Status::EndOfMedia          => "beginning or end of the medium reached",
+            Status::EndOfFile           => "end of file reached",
+            Status::InvalidLanguage     => "invalid language",
+            Status::CompromisedData     => "data security status unknown or compromised",
+            Status::IpAddressConflict   => "IP address conflict",
+            Status::HttpError           => "HTTP error",
+            Status::Unknown(code)       => return write!(f, "unknown status {:#x}", code),
+        };
+        write!(f, "{}", text)
+    }
+}
+
 impl From<Status> for core::result::Result<(), Status> {
     fn from(s: Status) -> Self {
         match s {
-            Status::Success => Ok(()),
-            err             => Err(err)
+            s if !s.is_error() => Ok(()),
+            err                => Err(err)
         }
     }
 }
@@ -67,18 +283,17 @@ impl Termination for () {
 
 impl Termination for ! {
     fn to_efi(&self) -> u64 {
-        return EFI_ERR;
+        match *self {}
     }
 }
 
+// Warnings (or success) in Err don't mean the image failed, they're
+// reported as EFI_SUCCESS
 impl<T> Termination for Result<T> {
     fn to_efi(&self) -> u64 {
         match *self {
-            Ok(_)       => EFI_SUCCESS,
-            Err(err)    => {
-                assert!(err != Status::Success);
-                err.into()
-            }
+            Err(err) if err.is_error()  => err.into(),
+            _                           => EFI_SUCCESS
         }
     }
 }
@@ -104,6 +319,39 @@ impl CStr16 {
     pub fn as_ptr(&self) -> *const u16 {
         return &self.data[0]
     }
+
+    // Wraps a NUL-terminated string returned by the firmware
+    pub unsafe fn from_ptr(ptr: *const u16) -> &'static CStr16 {
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        return &*(core::slice::from_raw_parts(ptr, len + 1) as *const _ as *const _);
+    }
+
+    // Compares the string (without the terminating NUL) to the start of `s`
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        let mut units = self.data.iter().take_while(|unit| **unit != 0);
+        return prefix.encode_utf16().all(|unit| units.next() == Some(&unit));
+    }
+
+    // Encodes `s` into `buf` as a NUL-terminated string, None if it
+    // doesn't fit
+    pub fn from_str_in<'a>(s: &str, buf: &'a mut [u16]) -> Option<&'a CStr16> {
+        if buf.is_empty() {
+            return None;
+        }
+        let mut len = 0;
+        for unit in s.encode_utf16() {
+            if len + 1 >= buf.len() {
+                return None;
+            }
+            buf[len] = unit;
+            len += 1;
+        }
+        buf[len] = 0;
+        return Some(unsafe {&*(&buf[..len + 1] as *const _ as *const _)});
+    }
 }
 
 impl fmt::Debug for CStr16 {
@@ -6,7 +6,8 @@ use super::{
     TableHeader,
     Status,
     MemoryMap,
-    MemoryDescriptor
+    MemoryDescriptor,
+    MemoryType
 };
 use crate::proto::Protocol;
 
@@ -19,20 +20,27 @@ pub enum LocateSearchType {
     ByProtocol
 }
 
+#[repr(C)]
+pub enum AllocateType {
+    AnyPages,
+    MaxAddress,
+    Address
+}
+
 #[repr(C)]
 pub struct BootServices {
     hdr:                            TableHeader,
     raise_tpl:                      *mut c_void,
     restore_tpl:                    *mut c_void,
-    allocate_pages:                 *mut c_void,
-    free_pages:                     *mut c_void,
+    allocate_pages:                 unsafe fn (AllocateType, u32, usize, *mut u64) -> u64,
+    free_pages:                     unsafe fn (u64, usize) -> u64,
     get_memory_map:                 unsafe fn (*mut usize,
                                                *mut MemoryDescriptor,
                                                *mut usize,
                                                *mut usize,
                                                *mut u32) -> u64,
-    allocate_pool:                  *mut c_void,
-    free_pool:                      *mut c_void,
+    allocate_pool:                  unsafe fn (u32, usize, *mut *mut c_void) -> u64,
+    free_pool:                      unsafe fn (*mut c_void) -> u64,
     create_event:                   *mut c_void,
     set_timer:                      *mut c_void,
     wait_for_event:                 unsafe fn (usize, *const Event, *mut usize) -> u64,
@@ -86,16 +94,58 @@ pub struct HandleBufferIterator {
 }
 
 impl BootServices {
+    // For MaxAddress and Address allocations `addr` specifies the limit
+    // or the exact address respectively
+    pub fn allocate_pages(&self,
+                          alloc_type: AllocateType,
+                          mem_type: MemoryType,
+                          count: usize,
+                          addr: usize) -> Result<usize, Status> {
+        let mut memory = addr as u64;
+        match Status::from(unsafe {
+            (self.allocate_pages)(
+                alloc_type,
+                mem_type.into(),
+                count,
+                &mut memory
+            )
+        }) {
+            s if !s.is_error() => Ok(memory as usize),
+            err                => Err(err)
+        }
+    }
+
+    pub fn free_pages(&self, addr: usize, count: usize) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.free_pages)(addr as u64, count)
+        }).into()
+    }
+
+    pub fn allocate_pool(&self, mem_type: MemoryType, size: usize) -> Result<*mut u8, Status> {
+        let mut buffer: *mut c_void = core::ptr::null_mut();
+        match Status::from(unsafe {
+            (self.allocate_pool)(mem_type.into(), size, &mut buffer)
+        }) {
+            s if !s.is_error() => Ok(buffer as *mut u8),
+            err                => Err(err)
+        }
+    }
+
+    pub fn free_pool(&self, buffer: *mut u8) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.free_pool)(buffer as *mut c_void)
+        }).into()
+    }
+
     pub fn get_memory_map(&self, out: &mut MemoryMap) -> Result<(), Status> {
         out.size = out.storage_ref.len();
         Status::from(unsafe {
-            let mut trash: u32 = 0;
             (self.get_memory_map)(
                 (&mut out.size)                 as *mut usize,
                 out.storage_ref.as_mut_ptr()    as *mut MemoryDescriptor,
                 (&mut out.key)                  as *mut usize,
                 (&mut out.descriptor_size)      as *mut usize,
-                (&mut trash)                    as *mut u32
+                (&mut out.descriptor_version)   as *mut u32
             )
         }).into()
     }
@@ -123,9 +173,15 @@ impl BootServices {
     }
 
     pub fn exit_boot_services(&self, map_key: usize) -> Result<(), Status> {
-        Status::from(unsafe {
+        match Status::from(unsafe {
             (self.exit_boot_services)(Handle::from(super::image_handle()), map_key)
-        }).into()
+        }) {
+            s if !s.is_error() => {
+                super::boot_services_exited();
+                Ok(())
+            },
+            err                => Err(err)
+        }
     }
 
     pub fn locate_protocol<T: Protocol>(&self) -> Result<&'static mut T, Status> {
@@ -139,8 +195,8 @@ impl BootServices {
                 (&mut proto_ptr) as *mut *mut c_void
             )
         }) {
-            Status::Success => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
-            err             => Err(err)
+            s if !s.is_error() => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
+            err                => Err(err)
         }
     }
 
@@ -155,8 +211,8 @@ impl BootServices {
                 &mut proto_ptr
             )
         }) {
-            Status::Success => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
-            err             => Err(err)
+            s if !s.is_error() => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
+            err                => Err(err)
         }
     }
 
@@ -178,8 +234,8 @@ impl BootServices {
                 attr
             )
         }) {
-            Status::Success => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
-            err             => Err(err)
+            s if !s.is_error() => Ok(unsafe {(proto_ptr as *mut T).as_mut()}.unwrap()),
+            err                => Err(err)
         }
     }
 
@@ -202,8 +258,8 @@ impl BootServices {
                 &mut iter.buffer
             )
         }) {
-            Status::Success => Ok(iter),
-            err             => Err(err)
+            s if !s.is_error() => Ok(iter),
+            err                => Err(err)
         }
     }
 }
@@ -1,8 +1,9 @@
 #![feature(never_type)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use core::ffi::c_void;
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 pub mod proto;
 pub use proto::*;
@@ -94,6 +95,16 @@ impl<'a> Iterator for ConfigurationTableIterator<'a> {
 }
 
 impl SystemTable {
+    // Extended input protocol of the console, not all firmware has it
+    pub fn con_in_ex(&self) -> Result<&'static mut SimpleTextInputExProtocol> {
+        self.boot_services.handle_protocol::<SimpleTextInputExProtocol>(self.console_in_handle)
+    }
+
+    // Display the console is drawn on, if it's on a GOP framebuffer
+    pub fn con_out_gop(&self) -> Result<&'static mut GraphicsOutputProtocol> {
+        self.boot_services.handle_protocol::<GraphicsOutputProtocol>(self.console_out_handle)
+    }
+
     pub fn config_iter(&self) -> ConfigurationTableIterator {
         ConfigurationTableIterator {
             st: self,
@@ -104,6 +115,8 @@ impl SystemTable {
 
 static mut SYSTEM_TABLE: *mut SystemTable = null_mut();
 static mut IMAGE_HANDLE: *mut ImageHandle = null_mut();
+// Cleared once ExitBootServices() succeeds
+static BOOT_SERVICES_ACTIVE: AtomicBool = AtomicBool::new(true);
 
 pub fn init(ih: *mut ImageHandle, st: *mut SystemTable) {
     if st.is_null() || ih.is_null() {
@@ -122,3 +135,11 @@ pub fn system_table() -> &'static mut SystemTable {
 pub fn image_handle() -> &'static mut ImageHandle {
     unsafe { &mut *IMAGE_HANDLE }
 }
+
+pub fn boot_services_active() -> bool {
+    BOOT_SERVICES_ACTIVE.load(Ordering::Relaxed)
+}
+
+fn boot_services_exited() {
+    BOOT_SERVICES_ACTIVE.store(false, Ordering::Relaxed);
+}
@@ -1,3 +1,186 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{BitOr, BitOrAssign};
+use crate::Status;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MemoryType {
+    Reserved,
+    LoaderCode,
+    LoaderData,
+    BootServicesCode,
+    BootServicesData,
+    RuntimeServicesCode,
+    RuntimeServicesData,
+    Conventional,
+    Unusable,
+    AcpiReclaim,
+    AcpiNvs,
+    MemoryMappedIo,
+    MemoryMappedIoPortSpace,
+    PalCode,
+    Persistent,
+    Unaccepted,
+    // 0x70000000 .. 0x7FFFFFFF
+    OemDefined(u32),
+    // 0x80000000 .. 0xFFFFFFFF
+    OsDefined(u32),
+    Invalid(u32)
+}
+
+impl From<u32> for MemoryType {
+    fn from(v: u32) -> Self {
+        use MemoryType::*;
+        match v {
+            0                           => Reserved,
+            1                           => LoaderCode,
+            2                           => LoaderData,
+            3                           => BootServicesCode,
+            4                           => BootServicesData,
+            5                           => RuntimeServicesCode,
+            6                           => RuntimeServicesData,
+            7                           => Conventional,
+            8                           => Unusable,
+            9                           => AcpiReclaim,
+            10                          => AcpiNvs,
+            11                          => MemoryMappedIo,
+            12                          => MemoryMappedIoPortSpace,
+            13                          => PalCode,
+            14                          => Persistent,
+            15                          => Unaccepted,
+            0x70000000 ..= 0x7FFFFFFF   => OemDefined(v),
+            0x80000000 ..= 0xFFFFFFFF   => OsDefined(v),
+            _                           => Invalid(v)
+        }
+    }
+}
+
+impl From<MemoryType> for u32 {
+    fn from(t: MemoryType) -> u32 {
+        use MemoryType::*;
+        match t {
+            Reserved                    => 0,
+            LoaderCode                  => 1,
+            LoaderData                  => 2,
+            BootServicesCode            => 3,
+            BootServicesData            => 4,
+            RuntimeServicesCode         => 5,
+            RuntimeServicesData         => 6,
+            Conventional                => 7,
+            Unusable                    => 8,
+            AcpiReclaim                 => 9,
+            AcpiNvs                     => 10,
+            MemoryMappedIo              => 11,
+            MemoryMappedIoPortSpace     => 12,
+            PalCode                     => 13,
+            Persistent                  => 14,
+            Unaccepted                  => 15,
+            OemDefined(v)               => v,
+            OsDefined(v)                => v,
+            Invalid(v)                  => v
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MemoryAttribute(u64);
+
+impl MemoryAttribute {
+    pub const UC:               MemoryAttribute = MemoryAttribute(1 << 0);
+    pub const WC:               MemoryAttribute = MemoryAttribute(1 << 1);
+    pub const WT:               MemoryAttribute = MemoryAttribute(1 << 2);
+    pub const WB:               MemoryAttribute = MemoryAttribute(1 << 3);
+    pub const UCE:              MemoryAttribute = MemoryAttribute(1 << 4);
+    pub const WP:               MemoryAttribute = MemoryAttribute(1 << 12);
+    pub const RP:               MemoryAttribute = MemoryAttribute(1 << 13);
+    pub const XP:               MemoryAttribute = MemoryAttribute(1 << 14);
+    pub const NV:               MemoryAttribute = MemoryAttribute(1 << 15);
+    pub const MORE_RELIABLE:    MemoryAttribute = MemoryAttribute(1 << 16);
+    pub const RO:               MemoryAttribute = MemoryAttribute(1 << 17);
+    pub const SP:               MemoryAttribute = MemoryAttribute(1 << 18);
+    pub const CPU_CRYPTO:       MemoryAttribute = MemoryAttribute(1 << 19);
+    pub const ISA_VALID:        MemoryAttribute = MemoryAttribute(1 << 62);
+    pub const RUNTIME:          MemoryAttribute = MemoryAttribute(1 << 63);
+
+    const NAMES: [(MemoryAttribute, &'static str); 15] = [
+        (Self::UC,              "UC"),
+        (Self::WC,              "WC"),
+        (Self::WT,              "WT"),
+        (Self::WB,              "WB"),
+        (Self::UCE,             "UCE"),
+        (Self::WP,              "WP"),
+        (Self::RP,              "RP"),
+        (Self::XP,              "XP"),
+        (Self::NV,              "NV"),
+        (Self::MORE_RELIABLE,   "MORE_RELIABLE"),
+        (Self::RO,              "RO"),
+        (Self::SP,              "SP"),
+        (Self::CPU_CRYPTO,      "CPU_CRYPTO"),
+        (Self::ISA_VALID,       "ISA_VALID"),
+        (Self::RUNTIME,         "RUNTIME"),
+    ];
+
+    pub const fn empty() -> Self {
+        MemoryAttribute(0)
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        MemoryAttribute(bits)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: MemoryAttribute) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for MemoryAttribute {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        MemoryAttribute(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MemoryAttribute {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for MemoryAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.0;
+        let mut first = true;
+
+        for (flag, name) in Self::NAMES.iter() {
+            if self.contains(*flag) {
+                if !first {
+                    write!(f, "|")?;
+                }
+                write!(f, "{}", name)?;
+                rest &= !flag.0;
+                first = false;
+            }
+        }
+
+        if rest != 0 {
+            if !first {
+                write!(f, "|")?;
+            }
+            write!(f, "0x{:x}", rest)?;
+        } else if first {
+            write!(f, "-")?;
+        }
+
+        Ok(())
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct MemoryDescriptor {
@@ -5,7 +188,7 @@ pub struct MemoryDescriptor {
     pub physical_start:     usize,
     pub virtual_start:      usize,
     pub number_of_pages:    u64,
-    pub attribute:          u64
+    pub attribute:          MemoryAttribute
 }
 
 impl MemoryDescriptor {
@@ -15,11 +198,27 @@ impl MemoryDescriptor {
     pub fn end(&self) -> usize {
         return self.physical_start + (self.number_of_pages as usize) * 0x1000;
     }
+    pub fn memory_type(&self) -> MemoryType {
+        return MemoryType::from(self._type);
+    }
     pub fn is_usable_now(&self) -> bool {
-        return self._type == 7;
+        return self.memory_type() == MemoryType::Conventional;
+    }
+    // Boot services memory is reclaimed by the time the kernel gets control,
+    // so it can hold data which is only written after ExitBootServices()
+    pub fn is_usable_after_exit(&self) -> bool {
+        return match self.memory_type() {
+            MemoryType::Conventional
+            | MemoryType::BootServicesCode
+            | MemoryType::BootServicesData  => true,
+            _                               => false
+        };
     }
 }
 
+// Decides whether a descriptor's memory may be used for placement
+pub type UsablePredicate = fn(&MemoryDescriptor) -> bool;
+
 pub struct MemoryMapIterator<'a> {
     mmap:       &'a MemoryMap<'a>,
     pos:        usize,
@@ -43,10 +242,36 @@ impl<'a> Iterator for MemoryMapIterator<'a> {
     }
 }
 
+pub struct MemoryMapIteratorMut<'a> {
+    ptr:        *mut u8,
+    pos:        usize,
+    count:      usize,
+    stride:     usize,
+    _marker:    PhantomData<&'a mut MemoryDescriptor>
+}
+
+impl<'a> Iterator for MemoryMapIteratorMut<'a> {
+    type Item = &'a mut MemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.count {
+            return None;
+        }
+
+        let item_ptr = unsafe { self.ptr.add(self.pos * self.stride) };
+        self.pos += 1;
+
+        unsafe {
+            return (item_ptr as *mut MemoryDescriptor).as_mut();
+        }
+    }
+}
+
 pub struct MemoryMap<'a> {
     pub storage_ref:        &'a mut [u8],
     pub key:                usize,
     pub descriptor_size:    usize,
+    pub descriptor_version: u32,
     pub size:               usize,
 }
 
@@ -55,6 +280,7 @@ impl<'a> MemoryMap<'a> {
         return MemoryMap {
             storage_ref: storage,
             descriptor_size: 0,
+            descriptor_version: 0,
             size: 0,
             key: 0
         };
@@ -72,19 +298,373 @@ impl<'a> MemoryMap<'a> {
         });
     }
 
+    pub fn iter_mut(&mut self) -> Option<MemoryMapIteratorMut> {
+        if self.size == 0 || self.descriptor_size == 0 {
+            return None;
+        }
+
+        return Some(MemoryMapIteratorMut {
+            ptr: self.storage_ref.as_mut_ptr(),
+            pos: 0,
+            count: self.size / self.descriptor_size,
+            stride: self.descriptor_size,
+            _marker: PhantomData
+        });
+    }
+
+    // Returns the usable descriptor covering the address, if any
+    fn usable_at(&self, addr: usize, usable: UsablePredicate) -> Option<&MemoryDescriptor> {
+        self.iter()
+            .into_iter()
+            .flatten()
+            .find(|item| usable(item) && addr >= item.begin() && addr < item.end())
+    }
+
+    // Returns the lowest address in [start, end) which is not covered
+    // by a usable descriptor. Holes in the map are treated as unusable.
+    pub fn first_unusable_with(&self,
+                               start: usize,
+                               end: usize,
+                               usable: UsablePredicate) -> Option<usize> {
+        let mut addr = start;
+        while addr < end {
+            match self.usable_at(addr, usable) {
+                Some(item) => addr = item.end(),
+                None       => return Some(addr)
+            }
+        }
+        return None;
+    }
+
+    pub fn first_unusable(&self, start: usize, end: usize) -> Option<usize> {
+        return self.first_unusable_with(start, end, MemoryDescriptor::is_usable_now);
+    }
+
+    pub fn is_range_usable(&self, start: usize, end: usize) -> bool {
+        return self.first_unusable(start, end).is_none();
+    }
+
     pub fn is_usable_now(&self, page: usize) -> bool {
-        for item in self.iter().unwrap() {
-            if item.is_usable_now() {
+        return self.is_range_usable(page & !0xFFF, (page & !0xFFF) + 0x1000);
+    }
+
+    pub fn free_regions_with(&self, usable: UsablePredicate) -> FreeRegionIterator {
+        return FreeRegionIterator {
+            mmap:   self,
+            inner:  self.iter(),
+            usable
+        };
+    }
+
+    pub fn free_regions(&self) -> FreeRegionIterator {
+        return self.free_regions_with(MemoryDescriptor::is_usable_now);
+    }
+
+    // Finds the lowest aligned address in [min_addr, max_addr) where
+    // `size` bytes of usable memory are available
+    pub fn find_free_with(&self,
+                          size: usize,
+                          align: usize,
+                          min_addr: usize,
+                          max_addr: usize,
+                          usable: UsablePredicate) -> Option<usize> {
+        let mut best: Option<usize> = None;
+
+        for region in self.free_regions_with(usable) {
+            let base = core::cmp::max(region.start, min_addr);
+            let start = (base + align - 1) & !(align - 1);
+            let limit = core::cmp::min(region.end, max_addr);
+
+            if start >= limit || limit - start < size {
                 continue;
             }
 
-            let aligned_begin = (item.begin() + 0xFFF) & !0xFFF;
-            let aligned_end = item.end() & !0xFFF;
+            if best.map_or(true, |addr| start < addr) {
+                best = Some(start);
+            }
+        }
+
+        return best;
+    }
+
+    pub fn find_free_above(&self,
+                           size: usize,
+                           align: usize,
+                           min_addr: usize,
+                           max_addr: usize) -> Option<usize> {
+        return self.find_free_with(size, align, min_addr, max_addr, MemoryDescriptor::is_usable_now);
+    }
+
+    pub fn find_free(&self, size: usize, align: usize, max_addr: usize) -> Option<usize> {
+        return self.find_free_above(size, align, 0, max_addr);
+    }
+
+    fn descriptor_mut(&mut self, index: usize) -> &mut MemoryDescriptor {
+        let offset = index * self.descriptor_size;
+        unsafe {
+            return &mut *(self.storage_ref.as_mut_ptr().add(offset) as *mut MemoryDescriptor);
+        }
+    }
+
+    // Splits the descriptor accepted by `filter` which contains `addr`,
+    // so that a descriptor starts there
+    fn split_at(&mut self, addr: usize, filter: UsablePredicate) -> Result<(), Status> {
+        let index = match self.iter()
+            .into_iter()
+            .flatten()
+            .position(|item| filter(item) && addr > item.begin() && addr < item.end()) {
+            Some(index) => index,
+            None        => return Ok(())
+        };
+
+        let stride = self.descriptor_size;
+        if self.size + stride > self.storage_ref.len() {
+            return Err(Status::BufferTooSmall);
+        }
+
+        let offset = index * stride;
+        self.storage_ref.copy_within(offset..self.size, offset + stride);
+        self.size += stride;
 
-            if page >= aligned_begin && page < aligned_end {
-                return false;
+        let head = self.descriptor_mut(index);
+        let pages = ((addr - head.begin()) / 0x1000) as u64;
+        head.number_of_pages = pages;
+
+        let tail = self.descriptor_mut(index + 1);
+        tail.physical_start += pages as usize * 0x1000;
+        if tail.virtual_start != 0 {
+            tail.virtual_start += pages as usize * 0x1000;
+        }
+        tail.number_of_pages -= pages;
+
+        return Ok(());
+    }
+
+    // Changes the type of descriptors accepted by `filter` within
+    // [start, end), splitting them at the edges of the range. The range
+    // is extended to page boundaries
+    pub fn set_range_type(&mut self,
+                          start: usize,
+                          end: usize,
+                          memory_type: MemoryType,
+                          filter: UsablePredicate) -> Result<(), Status> {
+        let start = start & !0xFFF;
+        let end = (end + 0xFFF) & !0xFFF;
+
+        self.split_at(start, filter)?;
+        self.split_at(end, filter)?;
+
+        for item in self.iter_mut().into_iter().flatten() {
+            if filter(item) && item.begin() >= start && item.end() <= end {
+                item._type = memory_type.into();
             }
         }
-        return true;
+
+        return Ok(());
+    }
+}
+
+// Physically contiguous range of usable memory, possibly
+// spanning several adjacent descriptors
+#[derive(Copy, Clone, Debug)]
+pub struct FreeRegion {
+    pub start:  usize,
+    pub end:    usize,
+}
+
+pub struct FreeRegionIterator<'a> {
+    mmap:       &'a MemoryMap<'a>,
+    inner:      Option<MemoryMapIterator<'a>>,
+    usable:     UsablePredicate,
+}
+
+impl<'a> Iterator for FreeRegionIterator<'a> {
+    type Item = FreeRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mmap = self.mmap;
+
+        // The map is not guaranteed to be sorted, so only yield regions
+        // starting at a descriptor no other usable descriptor ends at
+        while let Some(item) = self.inner.as_mut()?.next() {
+            if !(self.usable)(item) {
+                continue;
+            }
+            if item.begin() != 0 && mmap.usable_at(item.begin() - 1, self.usable).is_some() {
+                continue;
+            }
+
+            let mut end = item.end();
+            while let Some(next) = mmap.usable_at(end, self.usable) {
+                end = next.end();
+            }
+
+            return Some(FreeRegion {
+                start:  item.begin(),
+                end
+            });
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Firmware descriptors are usually larger than the structure
+    const STRIDE: usize = 48;
+
+    fn conventional(start: usize, end: usize) -> (MemoryType, usize, usize) {
+        (MemoryType::Conventional, start, end)
+    }
+
+    fn build<'a>(storage: &'a mut [u8], items: &[(MemoryType, usize, usize)]) -> MemoryMap<'a> {
+        let mut mmap = MemoryMap::new(storage);
+        mmap.descriptor_size = STRIDE;
+        mmap.size = items.len() * STRIDE;
+        for (i, (memory_type, start, end)) in items.iter().enumerate() {
+            *mmap.descriptor_mut(i) = MemoryDescriptor {
+                _type:              (*memory_type).into(),
+                physical_start:     *start,
+                virtual_start:      0,
+                number_of_pages:    ((end - start) / 0x1000) as u64,
+                attribute:          MemoryAttribute::WB
+            };
+        }
+        return mmap;
+    }
+
+    fn regions(mmap: &MemoryMap) -> Vec<(usize, usize)> {
+        return mmap.free_regions().map(|region| (region.start, region.end)).collect();
+    }
+
+    fn layout(mmap: &MemoryMap) -> Vec<(MemoryType, usize, usize)> {
+        return mmap.iter()
+            .into_iter()
+            .flatten()
+            .map(|item| (item.memory_type(), item.begin(), item.end()))
+            .collect();
+    }
+
+    #[test]
+    fn usable_at_bounds() {
+        let mut storage = [0u8; STRIDE * 2];
+        let mmap = build(&mut storage, &[
+            conventional(0x100000, 0x200000),
+            (MemoryType::LoaderData, 0x200000, 0x300000),
+        ]);
+
+        assert_eq!(mmap.usable_at(0x100000, MemoryDescriptor::is_usable_now).map(|d| d.begin()), Some(0x100000));
+        assert_eq!(mmap.usable_at(0x1FFFFF, MemoryDescriptor::is_usable_now).map(|d| d.begin()), Some(0x100000));
+        assert!(mmap.usable_at(0x200000, MemoryDescriptor::is_usable_now).is_none());
+        assert!(mmap.usable_at(0xFFFFF, MemoryDescriptor::is_usable_now).is_none());
+    }
+
+    #[test]
+    fn adjacent_descriptors_form_one_region() {
+        // Out of order on purpose, the map isn't guaranteed to be sorted
+        let mut storage = [0u8; STRIDE * 4];
+        let mmap = build(&mut storage, &[
+            conventional(0x200000, 0x300000),
+            (MemoryType::BootServicesData, 0x300000, 0x400000),
+            conventional(0x100000, 0x200000),
+            conventional(0x400000, 0x500000),
+        ]);
+
+        assert_eq!(regions(&mmap), [(0x100000, 0x300000), (0x400000, 0x500000)]);
+        assert_eq!(mmap.first_unusable(0x100000, 0x500000), Some(0x300000));
+        assert!(mmap.is_range_usable(0x180000, 0x280000));
+        assert_eq!(mmap.find_free(0x180000, 0x1000, usize::MAX), Some(0x100000));
+        assert_eq!(mmap.find_free(0x200001, 0x1000, usize::MAX), None);
+    }
+
+    #[test]
+    fn overlapping_descriptors() {
+        let mut storage = [0u8; STRIDE * 2];
+        let mmap = build(&mut storage, &[
+            conventional(0x200000, 0x400000),
+            conventional(0x100000, 0x300000),
+        ]);
+
+        assert_eq!(regions(&mmap), [(0x100000, 0x400000)]);
+        assert_eq!(mmap.first_unusable(0x100000, 0x400000), None);
+        assert_eq!(mmap.first_unusable(0x100000, 0x401000), Some(0x400000));
+        assert_eq!(mmap.find_free(0x300000, 0x1000, usize::MAX), Some(0x100000));
+    }
+
+    #[test]
+    fn last_region_at_top_of_memory() {
+        let mut storage = [0u8; STRIDE * 2];
+        let mmap = build(&mut storage, &[
+            conventional(0x100000, 0x200000),
+            conventional(0xFFF00000, 0x100000000),
+        ]);
+
+        assert_eq!(regions(&mmap), [(0x100000, 0x200000), (0xFFF00000, 0x100000000)]);
+        assert_eq!(mmap.first_unusable(0xFFF00000, 0x100000000), None);
+        assert_eq!(mmap.first_unusable(0xFFF00000, 0x100001000), Some(0x100000000));
+        // Exactly fills the region up to the limit
+        assert_eq!(mmap.find_free_above(0x100000, 0x1000, 0x200000, 0x100000000), Some(0xFFF00000));
+        assert_eq!(mmap.find_free_above(0x100000, 0x1000, 0x200000, 0xFFFFF000), None);
+        assert_eq!(mmap.find_free_above(0x1000, 0x1000, 0x200000, usize::MAX), Some(0xFFF00000));
+    }
+
+    #[test]
+    fn alignment_rounding() {
+        let mut storage = [0u8; STRIDE * 2];
+        let mmap = build(&mut storage, &[
+            conventional(0x101000, 0x400000),
+            conventional(0x1000000, 0x1200000),
+        ]);
+
+        assert_eq!(mmap.find_free(0x1000, 0x1000, usize::MAX), Some(0x101000));
+        assert_eq!(mmap.find_free(0x1000, 0x100000, usize::MAX), Some(0x200000));
+        assert_eq!(mmap.find_free(0x200000, 0x100000, usize::MAX), Some(0x200000));
+        // Rounding up leaves too little room in the first region
+        assert_eq!(mmap.find_free(0x300000, 0x100000, usize::MAX), None);
+        assert_eq!(mmap.find_free(0x1000, 0x400000, usize::MAX), Some(0x1000000));
+        // The minimum address is rounded up as well
+        assert_eq!(mmap.find_free_above(0x1000, 0x10000, 0x201001, usize::MAX), Some(0x210000));
+    }
+
+    #[test]
+    fn set_range_type_splits_descriptors() {
+        let mut storage = [0u8; STRIDE * 4];
+        let mut mmap = build(&mut storage, &[
+            conventional(0x100000, 0x200000),
+            (MemoryType::LoaderData, 0x200000, 0x300000),
+        ]);
+
+        // Extended to page boundaries
+        mmap.set_range_type(0x140800, 0x17F800, MemoryType::Unusable, MemoryDescriptor::is_usable_now).unwrap();
+        assert_eq!(layout(&mmap), [
+            (MemoryType::Conventional, 0x100000, 0x140000),
+            (MemoryType::Unusable, 0x140000, 0x180000),
+            (MemoryType::Conventional, 0x180000, 0x200000),
+            (MemoryType::LoaderData, 0x200000, 0x300000),
+        ]);
+        assert_eq!(regions(&mmap), [(0x100000, 0x140000), (0x180000, 0x200000)]);
+
+        // No room for another split
+        assert_eq!(mmap.set_range_type(0x101000, 0x102000, MemoryType::Unusable, MemoryDescriptor::is_usable_now),
+                   Err(Status::BufferTooSmall));
+    }
+
+    #[test]
+    fn set_range_type_respects_filter() {
+        let mut storage = [0u8; STRIDE * 4];
+        let mut mmap = build(&mut storage, &[
+            conventional(0x100000, 0x200000),
+            (MemoryType::LoaderData, 0x200000, 0x300000),
+        ]);
+
+        mmap.set_range_type(0x180000, 0x280000, MemoryType::Unusable, MemoryDescriptor::is_usable_now).unwrap();
+        assert_eq!(layout(&mmap), [
+            (MemoryType::Conventional, 0x100000, 0x180000),
+            (MemoryType::Unusable, 0x180000, 0x200000),
+            (MemoryType::LoaderData, 0x200000, 0x300000),
+        ]);
     }
 }
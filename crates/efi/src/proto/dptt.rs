@@ -0,0 +1,37 @@
+use crate::{Guid, Protocol, CStr16, DevicePathProtocol};
+
+#[repr(C)]
+pub struct DevicePathToTextProtocol {
+    convert_device_node_to_text:    unsafe fn (*const DevicePathProtocol, bool, bool) -> *const u16,
+    convert_device_path_to_text:    unsafe fn (*const DevicePathProtocol, bool, bool) -> *const u16
+}
+
+impl Protocol for DevicePathToTextProtocol {
+    const GUID: Guid = Guid {
+        data1:  0x8b843e20,
+        data2:  0x8132,
+        data3:  0x4852,
+        data4:  [0x90, 0xcc, 0x55, 0x1a, 0x4e, 0x4a, 0x7f, 0x1c]
+    };
+}
+
+impl DevicePathToTextProtocol {
+    // Full text form of the path, e.g. "PciRoot(0x0)/Pci(0x2,0x0)". The
+    // string is allocated from pool, the caller frees it with free_pool()
+    pub fn path_to_text(&self, path: &DevicePathProtocol) -> Option<&'static CStr16> {
+        let text = unsafe { (self.convert_device_path_to_text)(path, false, false) };
+        if text.is_null() {
+            return None;
+        }
+        return Some(unsafe { CStr16::from_ptr(text) });
+    }
+
+    // Same as path_to_text(), for a single node
+    pub fn node_to_text(&self, node: &DevicePathProtocol) -> Option<&'static CStr16> {
+        let text = unsafe { (self.convert_device_node_to_text)(node, false, false) };
+        if text.is_null() {
+            return None;
+        }
+        return Some(unsafe { CStr16::from_ptr(text) });
+    }
+}
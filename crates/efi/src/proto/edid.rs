@@ -0,0 +1,56 @@
+use crate::{Guid, Protocol};
+
+// EDID of the display attached to a GOP handle. "Active" is what the
+// firmware actually uses (possibly overridden by the platform),
+// "discovered" is the raw data read from the display. The data belongs
+// to the protocol instance and must not be freed
+
+#[repr(C)]
+pub struct EdidActiveProtocol {
+    size_of_edid:   u32,
+    edid:           *const u8
+}
+
+#[repr(C)]
+pub struct EdidDiscoveredProtocol {
+    size_of_edid:   u32,
+    edid:           *const u8
+}
+
+impl Protocol for EdidActiveProtocol {
+    const GUID: Guid = Guid {
+        data1:  0xbd8c1056,
+        data2:  0x9f36,
+        data3:  0x44ec,
+        data4:  [0x92, 0xa8, 0xa6, 0x33, 0x7f, 0x81, 0x79, 0x86]
+    };
+}
+
+impl Protocol for EdidDiscoveredProtocol {
+    const GUID: Guid = Guid {
+        data1:  0x1c0c34f6,
+        data2:  0xd380,
+        data3:  0x41fa,
+        data4:  [0xa0, 0x49, 0x8a, 0xd0, 0x6c, 0x1a, 0x66, 0xaa]
+    };
+}
+
+fn edid_slice(size: u32, edid: *const u8) -> Option<&'static [u8]> {
+    if size == 0 || edid.is_null() {
+        return None;
+    }
+    return Some(unsafe { core::slice::from_raw_parts(edid, size as usize) });
+}
+
+impl EdidActiveProtocol {
+    // None if there's no display or its EDID couldn't be read
+    pub fn edid(&self) -> Option<&'static [u8]> {
+        edid_slice(self.size_of_edid, self.edid)
+    }
+}
+
+impl EdidDiscoveredProtocol {
+    pub fn edid(&self) -> Option<&'static [u8]> {
+        edid_slice(self.size_of_edid, self.edid)
+    }
+}
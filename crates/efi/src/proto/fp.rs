@@ -71,8 +71,8 @@ impl File {
                 attr
             )
         }) {
-            Status::Success => Ok(File::from(ptr)),
-            err             => Err(err)
+            s if !s.is_error() => Ok(File::from(ptr)),
+            err                => Err(err)
         }
     }
 
@@ -85,8 +85,8 @@ impl File {
                 buf as *mut _ as *mut _
             )
         }) {
-            Status::Success => Ok(len),
-            err             => Err(err)
+            s if !s.is_error() => Ok(len),
+            err                => Err(err)
         }
     }
 
@@ -109,8 +109,8 @@ impl File {
                 statbuf.as_mut_ptr() as *mut _
             )
         }) {
-            Status::Success     => Ok(unsafe {core::mem::transmute(statbuf.as_ptr())}),
-            err                 => Err(err)
+            s if !s.is_error() => Ok(unsafe {core::mem::transmute(statbuf.as_ptr())}),
+            err                => Err(err)
         }
     }
 }
@@ -12,18 +12,41 @@ pub struct Mode {
     pub framebuffer_size:   usize
 }
 
+#[repr(u32)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum PixelFormat {
     PixelRedGreenBlueReserved8BitPerColor,
-    PixelBlueGreenRedReserved8BitPerColor
+    PixelBlueGreenRedReserved8BitPerColor,
+    // Layout given by pixel_information
+    PixelBitMask,
+    // No linear framebuffer, only Blt() can be used
+    PixelBltOnly
 }
 
 #[repr(C)]
+#[derive(Copy, Clone, Debug)]
 pub struct PixelBitmask {
-    red_mask:       u32,
-    green_mask:     u32,
-    blue_mask:      u32,
-    reserved_mask:  u32
+    pub red_mask:       u32,
+    pub green_mask:     u32,
+    pub blue_mask:      u32,
+    pub reserved_mask:  u32
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct BltPixel {
+    pub blue:       u8,
+    pub green:      u8,
+    pub red:        u8,
+    pub reserved:   u8
+}
+
+#[repr(C)]
+pub enum BltOperation {
+    VideoFill,
+    VideoToBltBuffer,
+    BufferToVideo,
+    VideoToVideo
 }
 
 #[repr(C)]
@@ -44,7 +67,13 @@ pub struct GraphicsOutputProtocol {
                                    *mut *mut ModeInformation) -> u64,
     set_mode:           unsafe fn (*mut GraphicsOutputProtocol,
                                    u32) -> u64,
-    blt:                *mut c_void,
+    blt:                unsafe fn (*mut GraphicsOutputProtocol,
+                                   *mut BltPixel,
+                                   BltOperation,
+                                   usize, usize,
+                                   usize, usize,
+                                   usize, usize,
+                                   usize) -> u64,
     mode:               &'static Mode
 }
 
@@ -72,13 +101,64 @@ impl GraphicsOutputProtocol {
         };
     }
 
+    pub fn current_mode(&self) -> &'static Mode {
+        self.mode
+    }
+
     pub fn set_mode(&mut self, num: u32) -> Result<&'static Mode, Status> {
         match Status::from(unsafe {
             (self.set_mode)(self as *mut GraphicsOutputProtocol, num)
         }) {
-            Status::Success => Ok(self.mode),
-            err             => Err(err)
+            s if !s.is_error() => Ok(self.mode),
+            err                => Err(err)
+        }
+    }
+
+    pub fn blt_fill(&mut self,
+                    color: BltPixel,
+                    x: usize, y: usize,
+                    width: usize, height: usize) -> Result<(), Status> {
+        let mut color = color;
+        Status::from(unsafe {
+            (self.blt)(self, &mut color, BltOperation::VideoFill, 0, 0, x, y, width, height, 0)
+        }).into()
+    }
+
+    // Copies a `width` x `height` rectangle at (src_x, src_y) of `buffer`,
+    // which is `stride` pixels wide, to (dst_x, dst_y) on the screen
+    pub fn blt_buffer_to_video(&mut self,
+                               buffer: &[BltPixel],
+                               stride: usize,
+                               src_x: usize, src_y: usize,
+                               dst_x: usize, dst_y: usize,
+                               width: usize, height: usize) -> Result<(), Status> {
+        if src_x + width > stride || (src_y + height) * stride > buffer.len() {
+            return Err(Status::InvalidParameter);
         }
+        Status::from(unsafe {
+            (self.blt)(self,
+                       buffer.as_ptr() as *mut BltPixel,
+                       BltOperation::BufferToVideo,
+                       src_x, src_y,
+                       dst_x, dst_y,
+                       width, height,
+                       stride * core::mem::size_of::<BltPixel>())
+        }).into()
+    }
+
+    pub fn blt_video_to_video(&mut self,
+                              src_x: usize, src_y: usize,
+                              dst_x: usize, dst_y: usize,
+                              width: usize, height: usize) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.blt)(self,
+                       core::ptr::null_mut(),
+                       BltOperation::VideoToVideo,
+                       src_x, src_y,
+                       dst_x, dst_y,
+                       width, height,
+                       0)
+        }).into()
     }
 
     // TODO: pixel format
@@ -92,6 +172,44 @@ impl GraphicsOutputProtocol {
     }
 }
 
+impl ModeInformation {
+    // Channel layout of a pixel, None for Blt-only modes
+    pub fn bitmask(&self) -> Option<PixelBitmask> {
+        match self.pixel_format {
+            PixelFormat::PixelRedGreenBlueReserved8BitPerColor => Some(PixelBitmask {
+                red_mask:       0x000000FF,
+                green_mask:     0x0000FF00,
+                blue_mask:      0x00FF0000,
+                reserved_mask:  0xFF000000
+            }),
+            PixelFormat::PixelBlueGreenRedReserved8BitPerColor => Some(PixelBitmask {
+                red_mask:       0x00FF0000,
+                green_mask:     0x0000FF00,
+                blue_mask:      0x000000FF,
+                reserved_mask:  0xFF000000
+            }),
+            PixelFormat::PixelBitMask => Some(self.pixel_information),
+            PixelFormat::PixelBltOnly => None
+        }
+    }
+
+    // Size of a framebuffer pixel in bits, rounded up to whole bytes.
+    // None if the mode has no framebuffer
+    pub fn bits_per_pixel(&self) -> Option<u32> {
+        let mask = self.bitmask()?;
+        let all = mask.red_mask | mask.green_mask | mask.blue_mask | mask.reserved_mask;
+        if all == 0 {
+            return None;
+        }
+        return Some((32 - all.leading_zeros() + 7) & !7);
+    }
+
+    // Scanline length in bytes, which may include padding
+    pub fn pitch(&self) -> Option<u32> {
+        return Some(self.pixels_per_scanline * self.bits_per_pixel()? / 8);
+    }
+}
+
 impl<'a> Iterator for ModeIterator<'a> {
     type Item = (u32, &'static ModeInformation);
 
@@ -111,7 +229,7 @@ impl<'a> Iterator for ModeIterator<'a> {
                 (&mut mode) as *mut *mut ModeInformation
             )
         }) {
-            Status::Success => {
+            s if !s.is_error() => {
                 self.number += 1;
                 unsafe {mode.as_ref()}.map(|x| { (self.number - 1, x) })
             },
@@ -121,6 +239,11 @@ impl<'a> Iterator for ModeIterator<'a> {
 }
 
 impl Mode {
+    // Information of the current mode
+    pub fn info(&self) -> Option<&'static ModeInformation> {
+        unsafe { (self.info as *const ModeInformation).as_ref() }
+    }
+
     pub fn framebuffer(&self) -> &mut [u32] {
         return unsafe {
             core::slice::from_raw_parts_mut(self.framebuffer_base as *mut u32, self.framebuffer_size)
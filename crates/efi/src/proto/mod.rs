@@ -1,10 +1,14 @@
 pub mod stop;
 pub mod stip;
+pub mod stipex;
 pub mod gop;
 pub mod lip;
 pub mod dpp;
+pub mod dptt;
 pub mod sfsp;
 pub mod fp;
+pub mod mpsp;
+pub mod edid;
 
 pub trait Protocol {
     const GUID: super::Guid;
@@ -12,9 +16,13 @@ pub trait Protocol {
 }
 
 pub use stop::SimpleTextOutputProtocol;
-pub use stip::SimpleTextInputProtocol;
+pub use stip::{SimpleTextInputProtocol, InputKey};
+pub use stipex::SimpleTextInputExProtocol;
 pub use gop::GraphicsOutputProtocol;
 pub use lip::LoadedImageProtocol;
 pub use dpp::DevicePathProtocol;
+pub use dptt::DevicePathToTextProtocol;
 pub use sfsp::SimpleFileSystemProtocol;
 pub use fp::{FileProtocol, File};
+pub use mpsp::MpServicesProtocol;
+pub use edid::{EdidActiveProtocol, EdidDiscoveredProtocol};
@@ -0,0 +1,99 @@
+use crate::{Status, Guid, Protocol};
+use core::ffi::c_void;
+
+pub const PROCESSOR_AS_BSP_BIT:         u32 = 1 << 0;
+pub const PROCESSOR_ENABLED_BIT:        u32 = 1 << 1;
+pub const PROCESSOR_HEALTH_STATUS_BIT:  u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CpuPhysicalLocation {
+    pub package:    u32,
+    pub core:       u32,
+    pub thread:     u32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessorInformation {
+    pub processor_id:           u64,
+    pub status_flag:            u32,
+    pub location:               CpuPhysicalLocation,
+    // Only filled in with CPU_V2_EXTENDED_TOPOLOGY requests
+    extended_information:       [u32; 6]
+}
+
+#[repr(C)]
+pub struct MpServicesProtocol {
+    get_number_of_processors:   unsafe fn (*mut MpServicesProtocol, *mut usize, *mut usize) -> u64,
+    get_processor_info:         unsafe fn (*mut MpServicesProtocol,
+                                           usize,
+                                           *mut ProcessorInformation) -> u64,
+    startup_all_aps:            *mut c_void,
+    startup_this_ap:            *mut c_void,
+    switch_bsp:                 *mut c_void,
+    enable_disable_ap:          *mut c_void,
+    who_am_i:                   unsafe fn (*mut MpServicesProtocol, *mut usize) -> u64
+}
+
+impl Protocol for MpServicesProtocol {
+    const GUID: Guid = Guid {
+        data1: 0x3fdda605,
+        data2: 0xa76e,
+        data3: 0x4f46,
+        data4: [0xad, 0x29, 0x12, 0xf4, 0x53, 0x1b, 0x3d, 0x08]
+    };
+}
+
+impl ProcessorInformation {
+    pub fn is_bsp(&self) -> bool {
+        (self.status_flag & PROCESSOR_AS_BSP_BIT) != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        (self.status_flag & PROCESSOR_ENABLED_BIT) != 0
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        (self.status_flag & PROCESSOR_HEALTH_STATUS_BIT) != 0
+    }
+}
+
+impl MpServicesProtocol {
+    // Returns (total, enabled) processor counts
+    pub fn get_number_of_processors(&mut self) -> Result<(usize, usize), Status> {
+        let mut total = 0usize;
+        let mut enabled = 0usize;
+        match Status::from(unsafe {
+            (self.get_number_of_processors)(self, &mut total, &mut enabled)
+        }) {
+            s if !s.is_error() => Ok((total, enabled)),
+            err                => Err(err)
+        }
+    }
+
+    pub fn get_processor_info(&mut self, number: usize) -> Result<ProcessorInformation, Status> {
+        let mut info = ProcessorInformation {
+            processor_id:           0,
+            status_flag:            0,
+            location:               CpuPhysicalLocation { package: 0, core: 0, thread: 0 },
+            extended_information:   [0; 6]
+        };
+        match Status::from(unsafe {
+            (self.get_processor_info)(self, number, &mut info)
+        }) {
+            s if !s.is_error() => Ok(info),
+            err                => Err(err)
+        }
+    }
+
+    pub fn who_am_i(&mut self) -> Result<usize, Status> {
+        let mut number = 0usize;
+        match Status::from(unsafe {
+            (self.who_am_i)(self, &mut number)
+        }) {
+            s if !s.is_error() => Ok(number),
+            err                => Err(err)
+        }
+    }
+}
@@ -26,8 +26,8 @@ impl SimpleFileSystemProtocol {
                 &mut root
             )
         }) {
-            Status::Success => Ok(File::from(root)),
-            err             => Err(err)
+            s if !s.is_error() => Ok(File::from(root)),
+            err                => Err(err)
         }
     }
 }
@@ -1,7 +1,19 @@
 use crate::{Status, Guid, Protocol, Event, system_table};
 
+// Scan codes of keys without a character
+pub const SCAN_NULL:    u16 = 0x00;
+pub const SCAN_UP:      u16 = 0x01;
+pub const SCAN_DOWN:    u16 = 0x02;
+pub const SCAN_RIGHT:   u16 = 0x03;
+pub const SCAN_LEFT:    u16 = 0x04;
+pub const SCAN_HOME:    u16 = 0x05;
+pub const SCAN_END:     u16 = 0x06;
+pub const SCAN_INSERT:  u16 = 0x07;
+pub const SCAN_DELETE:  u16 = 0x08;
+pub const SCAN_ESC:     u16 = 0x17;
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct InputKey {
     pub scan_code:      u16,
     pub unicode_char:   u16
@@ -50,8 +62,8 @@ impl SimpleTextInputProtocol {
                 (&mut stroke) as *mut InputKey
             )
         }) {
-            Status::Success => Ok(stroke),
-            err             => Err(err)
+            s if !s.is_error() => Ok(stroke),
+            err                => Err(err)
         }
     }
 }
@@ -0,0 +1,165 @@
+use crate::{Status, Guid, Protocol, Event, InputKey, system_table};
+use core::ffi::c_void;
+
+// key_shift_state bits, only meaningful with SHIFT_STATE_VALID set
+pub const SHIFT_STATE_VALID:      u32 = 0x80000000;
+pub const RIGHT_SHIFT_PRESSED:    u32 = 0x00000001;
+pub const LEFT_SHIFT_PRESSED:     u32 = 0x00000002;
+pub const RIGHT_CONTROL_PRESSED:  u32 = 0x00000004;
+pub const LEFT_CONTROL_PRESSED:   u32 = 0x00000008;
+pub const RIGHT_ALT_PRESSED:      u32 = 0x00000010;
+pub const LEFT_ALT_PRESSED:       u32 = 0x00000020;
+pub const RIGHT_LOGO_PRESSED:     u32 = 0x00000040;
+pub const LEFT_LOGO_PRESSED:      u32 = 0x00000080;
+pub const MENU_KEY_PRESSED:       u32 = 0x00000100;
+pub const SYS_REQ_PRESSED:        u32 = 0x00000200;
+
+// key_toggle_state bits, only meaningful with TOGGLE_STATE_VALID set
+pub const TOGGLE_STATE_VALID:     u8 = 0x80;
+// Report modifier-only key presses as keystrokes with no key
+pub const KEY_STATE_EXPOSED:      u8 = 0x40;
+pub const SCROLL_LOCK_ACTIVE:     u8 = 0x01;
+pub const NUM_LOCK_ACTIVE:        u8 = 0x02;
+pub const CAPS_LOCK_ACTIVE:       u8 = 0x04;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyState {
+    pub key_shift_state:    u32,
+    pub key_toggle_state:   u8
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct KeyData {
+    pub key:        InputKey,
+    pub key_state:  KeyState
+}
+
+// Called by the firmware at TPL_CALLBACK when a registered key is pressed
+pub type KeyNotifyFunction = extern "C" fn (*const KeyData) -> u64;
+
+#[repr(C)]
+pub struct SimpleTextInputExProtocol {
+    reset:                  unsafe fn (*mut SimpleTextInputExProtocol, bool) -> u64,
+    read_key_stroke_ex:     unsafe fn (*mut SimpleTextInputExProtocol, *mut KeyData) -> u64,
+    wait_for_key_ex:        Event,
+    set_state:              unsafe fn (*mut SimpleTextInputExProtocol, *const u8) -> u64,
+    register_key_notify:    unsafe fn (*mut SimpleTextInputExProtocol,
+                                       *const KeyData,
+                                       KeyNotifyFunction,
+                                       *mut *mut c_void) -> u64,
+    unregister_key_notify:  unsafe fn (*mut SimpleTextInputExProtocol, *mut c_void) -> u64
+}
+
+impl Protocol for SimpleTextInputExProtocol {
+    const GUID: Guid = Guid {
+        data1: 0xdd9e7534,
+        data2: 0x7762,
+        data3: 0x4698,
+        data4: [0x8c, 0x14, 0xf5, 0x85, 0x17, 0xa6, 0x25, 0xaa]
+    };
+}
+
+impl KeyState {
+    pub fn shift(&self) -> bool {
+        self.is_set(LEFT_SHIFT_PRESSED | RIGHT_SHIFT_PRESSED)
+    }
+
+    pub fn control(&self) -> bool {
+        self.is_set(LEFT_CONTROL_PRESSED | RIGHT_CONTROL_PRESSED)
+    }
+
+    pub fn alt(&self) -> bool {
+        self.is_set(LEFT_ALT_PRESSED | RIGHT_ALT_PRESSED)
+    }
+
+    fn is_set(&self, bits: u32) -> bool {
+        (self.key_shift_state & SHIFT_STATE_VALID) != 0 && (self.key_shift_state & bits) != 0
+    }
+}
+
+impl SimpleTextInputExProtocol {
+    pub fn reset(&mut self, extended_verification: bool) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.reset)(self as *mut SimpleTextInputExProtocol, extended_verification)
+        }).into()
+    }
+
+    pub fn read_key_stroke_ex(&mut self) -> Result<KeyData, Status> {
+        let mut data = KeyData {
+            key:        InputKey { scan_code: 0, unicode_char: 0 },
+            key_state:  KeyState::default()
+        };
+        match Status::from(unsafe {
+            (self.read_key_stroke_ex)(self as *mut SimpleTextInputExProtocol, &mut data)
+        }) {
+            s if !s.is_error() => Ok(data),
+            err                => Err(err)
+        }
+    }
+
+    // Like read_key_stroke_ex(), but doesn't fail when no key is pending:
+    // firmware may still report the current modifier state then
+    pub fn read_key_or_state(&mut self) -> Result<(Option<InputKey>, KeyState), Status> {
+        let mut data = KeyData {
+            key:        InputKey { scan_code: 0, unicode_char: 0 },
+            key_state:  KeyState::default()
+        };
+        match Status::from(unsafe {
+            (self.read_key_stroke_ex)(self as *mut SimpleTextInputExProtocol, &mut data)
+        }) {
+            s if !s.is_error() => Ok((Some(data.key), data.key_state)),
+            Status::NotReady   => Ok((None, data.key_state)),
+            err                => Err(err)
+        }
+    }
+
+    pub fn read_key_blocking_ex(&mut self) -> Result<KeyData, Status> {
+        system_table().boot_services.wait_for_event(self.wait_for_key_ex)?;
+        loop {
+            match self.read_key_stroke_ex() {
+                Err(Status::NotReady)   => continue,
+                res                     => return res
+            }
+        }
+    }
+
+    // Sets the toggle state (lock keys, KEY_STATE_EXPOSED), TOGGLE_STATE_VALID
+    // is added automatically
+    pub fn set_state(&mut self, toggle_state: u8) -> Result<(), Status> {
+        let state = toggle_state | TOGGLE_STATE_VALID;
+        Status::from(unsafe {
+            (self.set_state)(self as *mut SimpleTextInputExProtocol, &state)
+        }).into()
+    }
+
+    // Calls `function` whenever `key` is pressed with the modifiers in
+    // `shift_state` (SHIFT_STATE_VALID is added automatically). Returns
+    // the handle to unregister the notification with
+    pub fn register_key_notify(&mut self,
+                               key: InputKey,
+                               shift_state: u32,
+                               function: KeyNotifyFunction) -> Result<*mut c_void, Status> {
+        let data = KeyData {
+            key,
+            key_state:  KeyState {
+                key_shift_state:    shift_state | SHIFT_STATE_VALID,
+                key_toggle_state:   0
+            }
+        };
+        let mut handle: *mut c_void = core::ptr::null_mut();
+        match Status::from(unsafe {
+            (self.register_key_notify)(self as *mut SimpleTextInputExProtocol, &data, function, &mut handle)
+        }) {
+            s if !s.is_error() => Ok(handle),
+            err                => Err(err)
+        }
+    }
+
+    pub fn unregister_key_notify(&mut self, handle: *mut c_void) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.unregister_key_notify)(self as *mut SimpleTextInputExProtocol, handle)
+        }).into()
+    }
+}
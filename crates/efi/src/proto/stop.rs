@@ -1,12 +1,52 @@
 use crate::{Status, Guid, Protocol};
 
-use core::ffi::c_void;
 use core::fmt;
 
+#[repr(C)]
+pub struct Mode {
+    pub max_mode:       i32,
+    pub mode:           i32,
+    pub attribute:      i32,
+    pub cursor_column:  i32,
+    pub cursor_row:     i32,
+    pub cursor_visible: bool
+}
+
+// Text colours, all of them can be used as foreground, only the first 8
+// as background
+#[repr(usize)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Color {
+    Black,
+    Blue,
+    Green,
+    Cyan,
+    Red,
+    Magenta,
+    Brown,
+    LightGray,
+    DarkGray,
+    LightBlue,
+    LightGreen,
+    LightCyan,
+    LightRed,
+    LightMagenta,
+    Yellow,
+    White
+}
+
 #[repr(C)]
 pub struct SimpleTextOutputProtocol {
-    fn_reset: *mut c_void,
-    fn_output_string: unsafe fn(&SimpleTextOutputProtocol, s: *const i16) -> u64
+    fn_reset:               unsafe fn (&SimpleTextOutputProtocol, bool) -> u64,
+    fn_output_string:       unsafe fn (&SimpleTextOutputProtocol, *const u16) -> u64,
+    fn_test_string:         unsafe fn (&SimpleTextOutputProtocol, *const u16) -> u64,
+    fn_query_mode:          unsafe fn (&SimpleTextOutputProtocol, usize, *mut usize, *mut usize) -> u64,
+    fn_set_mode:            unsafe fn (&SimpleTextOutputProtocol, usize) -> u64,
+    fn_set_attribute:       unsafe fn (&SimpleTextOutputProtocol, usize) -> u64,
+    fn_clear_screen:        unsafe fn (&SimpleTextOutputProtocol) -> u64,
+    fn_set_cursor_position: unsafe fn (&SimpleTextOutputProtocol, usize, usize) -> u64,
+    fn_enable_cursor:       unsafe fn (&SimpleTextOutputProtocol, bool) -> u64,
+    mode:                   &'static Mode
 }
 
 impl Protocol for SimpleTextOutputProtocol {
@@ -19,27 +59,71 @@ impl Protocol for SimpleTextOutputProtocol {
 }
 
 impl SimpleTextOutputProtocol {
+    pub fn reset(&self, extended_verification: bool) -> Result<(), Status> {
+        Status::from(unsafe { (self.fn_reset)(self, extended_verification) }).into()
+    }
+
     // Not the original name provided by EFI, but whatever
-    pub fn output_char16_string(&self, s: *const i16) -> Status {
+    pub fn output_char16_string(&self, s: *const u16) -> Status {
         Status::from(unsafe { (self.fn_output_string)(self, s) })
     }
 
+    // Checks if every character of a NUL-terminated string can be displayed
+    pub fn test_char16_string(&self, s: *const u16) -> Status {
+        Status::from(unsafe { (self.fn_test_string)(self, s) })
+    }
+
+    // Returns (columns, rows) of the text mode
+    pub fn query_mode(&self, mode: usize) -> Result<(usize, usize), Status> {
+        let mut columns = 0usize;
+        let mut rows = 0usize;
+        match Status::from(unsafe { (self.fn_query_mode)(self, mode, &mut columns, &mut rows) }) {
+            s if !s.is_error() => Ok((columns, rows)),
+            err                => Err(err)
+        }
+    }
+
+    // Also clears the screen
+    pub fn set_mode(&self, mode: usize) -> Result<(), Status> {
+        Status::from(unsafe { (self.fn_set_mode)(self, mode) }).into()
+    }
+
+    pub fn set_color(&self, foreground: Color, background: Color) -> Result<(), Status> {
+        let attribute = foreground as usize | ((background as usize & 0x7) << 4);
+        Status::from(unsafe { (self.fn_set_attribute)(self, attribute) }).into()
+    }
+
+    // Fills the screen with the background colour, the cursor goes to (0, 0)
+    pub fn clear_screen(&self) -> Result<(), Status> {
+        Status::from(unsafe { (self.fn_clear_screen)(self) }).into()
+    }
+
+    pub fn set_cursor_position(&self, column: usize, row: usize) -> Result<(), Status> {
+        Status::from(unsafe { (self.fn_set_cursor_position)(self, column, row) }).into()
+    }
+
+    pub fn enable_cursor(&self, visible: bool) -> Result<(), Status> {
+        Status::from(unsafe { (self.fn_enable_cursor)(self, visible) }).into()
+    }
+
+    pub fn mode(&self) -> &'static Mode {
+        self.mode
+    }
+
+    // Converts `s` to UTF-16 in fixed-size chunks. Surrogate pairs are
+    // never split between chunks
     pub fn output_string(&self, s: &str) {
-        let mut buf = [0i16; 64];
-        let mut iter = s.bytes();
+        let mut buf = [0u16; 64];
         let mut i = 0;
 
-        while let Some(byte) = iter.next() {
-            if i == 63 {
+        for c in s.chars() {
+            // Room for a pair and the terminating NUL
+            if i + 2 >= buf.len() {
                 buf[i] = 0;
-                i = 0;
-
-                // Output chunk
                 self.output_char16_string(buf.as_ptr());
-            } else {
-                buf[i] = byte as i16;
-                i += 1;
+                i = 0;
             }
+            i += c.encode_utf16(&mut buf[i..]).len();
         }
 
         if i != 0 {
@@ -1,10 +1,23 @@
-use crate::{TableHeader, Status};
+use crate::{TableHeader, Status, MemoryMap, MemoryDescriptor};
 use core::ffi::c_void;
 
 #[repr(C)]
 pub struct RuntimeServices {
-    hdr:        TableHeader,
-    get_time:   unsafe fn (*mut u64, *mut c_void) -> u64
+    hdr:                            TableHeader,
+    get_time:                       unsafe fn (*mut u64, *mut c_void) -> u64,
+    set_time:                       *mut c_void,
+    get_wakeup_time:                *mut c_void,
+    set_wakeup_time:                *mut c_void,
+    set_virtual_address_map:        unsafe fn (usize, usize, u32, *mut MemoryDescriptor) -> u64,
+    convert_pointer:                *mut c_void,
+    get_variable:                   *mut c_void,
+    get_next_variable_name:         *mut c_void,
+    set_variable:                   *mut c_void,
+    get_next_high_monotonic_count:  *mut c_void,
+    reset_system:                   *mut c_void,
+    update_capsule:                 *mut c_void,
+    query_capsule_capabilities:     *mut c_void,
+    query_variable_info:            *mut c_void
 }
 
 impl RuntimeServices {
@@ -13,8 +26,22 @@ impl RuntimeServices {
         match Status::from(unsafe {
             (self.get_time)((&mut time) as *mut u64, core::ptr::null_mut())
         }) {
-            Status::Success => Ok(time),
-            err             => Err(err)
+            s if !s.is_error() => Ok(time),
+            err                => Err(err)
         }
     }
+
+    // Can only be called once, after ExitBootServices(). The map must be
+    // the one the boot services were terminated with, with virtual_start
+    // filled in for EFI_MEMORY_RUNTIME descriptors
+    pub fn set_virtual_address_map(&self, mmap: &mut MemoryMap) -> Result<(), Status> {
+        Status::from(unsafe {
+            (self.set_virtual_address_map)(
+                mmap.size,
+                mmap.descriptor_size,
+                mmap.descriptor_version,
+                mmap.storage_ref.as_mut_ptr() as *mut MemoryDescriptor
+            )
+        }).into()
+    }
 }
@@ -0,0 +1,371 @@
+// Definitions shared by the yboot2 loader and kernels booted by it. The
+// structures mirror include/protocol.h field by field
+#![no_std]
+
+pub mod video;
+#[cfg(feature = "load-protocol")]
+use core::convert::TryFrom;
+#[cfg(any(feature = "kernel-protocol", feature = "load-protocol"))]
+use video::ModeSelect;
+use video::PixelFormat;
+
+// Kernel-requested features, "flags" field of the header
+pub const FLAG_VIDEO:       u64 = 1 << 0;
+pub const FLAG_INITRD:      u64 = 1 << 1;
+pub const FLAG_UPPER:       u64 = 1 << 2;
+pub const FLAG_LA57:        u64 = 1 << 3;
+pub const FLAG_EFI_RUNTIME: u64 = 1 << 4;
+pub const FLAG_SMP_PARK:    u64 = 1 << 5;
+
+pub const CMDLINE_SIZE:     usize = 256;
+pub const MAX_ALLOCS:       usize = 8;
+
+// "video_output" when the firmware's default GOP instance was used
+pub const VIDEO_OUTPUT_DEFAULT: u64 = !0;
+
+pub trait Magic {
+    const KERNEL_MAGIC: [u8; 8];
+    const LOADER_MAGIC: [u8; 8];
+}
+
+#[repr(C)]
+pub struct Header {
+    pub kernel_magic:   [u8; 8],
+    pub loader_magic:   [u8; 8],
+    pub flags:          u64,
+}
+
+#[repr(C)]
+pub struct MemoryMapInfo {
+    pub address:        u64,
+    pub size:           u32,
+    pub entsize:        u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct VideoInfo {
+    pub width:          u32,
+    pub height:         u32,
+    pub format:         PixelFormat,
+    pub framebuffer:    u64,
+    pub pitch:          u64,
+}
+
+// Physically contiguous region requested by the kernel, "base" is set by
+// the loader (0 if it couldn't be satisfied)
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct AllocRequest {
+    pub size:           u64,
+    pub align:          u64,
+    pub max_address:    u64,
+    pub base:           u64,
+}
+
+#[repr(C)]
+pub struct ProtoV1 {
+    pub hdr:                  Header,
+
+    pub memory_map:           MemoryMapInfo,
+
+    pub video_width:          u32,
+    pub video_height:         u32,
+    pub video_format:         u32,
+    pub video_select:         u32,
+    pub video_framebuffer:    u64,
+    pub video_pitch:          u64,
+
+    pub elf_symtab_hdr:       u64,
+    pub elf_symtab_data:      u64,
+    pub elf_strtab_hdr:       u64,
+    pub elf_strtab_data:      u64,
+
+    pub initrd_base:          u64,
+    pub initrd_size:          u64,
+
+    pub rsdp:                 u64,
+
+    pub cmdline:              [u8; CMDLINE_SIZE],
+
+    pub pat:                  u64,
+    pub efi_runtime:          u64,
+
+    pub stack_base:           u64,
+    pub stack_size:           u64,
+
+    pub handoff_base:         u64,
+    pub handoff_size:         u64,
+
+    pub cpu_table:            u64,
+    pub cpu_count:            u64,
+
+    pub allocs:               [AllocRequest; MAX_ALLOCS],
+
+    pub video_columns:        u32,
+    pub video_rows:           u32,
+
+    pub video_red_mask:       u32,
+    pub video_green_mask:     u32,
+    pub video_blue_mask:      u32,
+    pub video_reserved_mask:  u32,
+
+    pub edid_data:            u64,
+    pub edid_size:            u64,
+
+    pub log:                  u64,
+
+    pub video_output:         u64,
+    pub video_output_path:    u64,
+}
+
+impl Magic for ProtoV1 {
+    const KERNEL_MAGIC: [u8; 8] = 0xA197A9B007B007u64.to_le_bytes();
+    const LOADER_MAGIC: [u8; 8] = 0x700B700B9A791Au64.to_le_bytes();
+}
+
+#[cfg(feature = "kernel-protocol")]
+impl ProtoV1 {
+    // Structure to place in the kernel image with the loader-written
+    // fields cleared. Requests (e.g. the video mode) are filled in with
+    // struct update syntax
+    pub const fn new(flags: u64) -> Self {
+        return ProtoV1 {
+            hdr: Header {
+                kernel_magic:   Self::KERNEL_MAGIC,
+                loader_magic:   [0; 8],
+                flags,
+            },
+
+            memory_map: MemoryMapInfo {
+                address:        0,
+                size:           0,
+                entsize:        0,
+            },
+
+            video_width:          0,
+            video_height:         0,
+            video_format:         PixelFormat::LfbRgb32 as u32,
+            video_select:         ModeSelect::Exact as u32,
+            video_framebuffer:    0,
+            video_pitch:          0,
+
+            elf_symtab_hdr:       0,
+            elf_symtab_data:      0,
+            elf_strtab_hdr:       0,
+            elf_strtab_data:      0,
+
+            initrd_base:          0,
+            initrd_size:          0,
+
+            rsdp:                 0,
+
+            cmdline:              [0; CMDLINE_SIZE],
+
+            pat:                  0,
+            efi_runtime:          0,
+
+            stack_base:           0,
+            stack_size:           0,
+
+            handoff_base:         0,
+            handoff_size:         0,
+
+            cpu_table:            0,
+            cpu_count:            0,
+
+            allocs:               [AllocRequest { size: 0, align: 0, max_address: 0, base: 0 }; MAX_ALLOCS],
+
+            video_columns:        0,
+            video_rows:           0,
+
+            video_red_mask:       0,
+            video_green_mask:     0,
+            video_blue_mask:      0,
+            video_reserved_mask:  0,
+
+            edid_data:            0,
+            edid_size:            0,
+
+            log:                  0,
+
+            video_output:         0,
+            video_output_path:    0,
+        };
+    }
+
+    // Whether a yboot2 loader has filled in the structure
+    pub fn is_loaded(&self) -> bool {
+        return self.hdr.loader_magic == Self::LOADER_MAGIC;
+    }
+}
+
+#[cfg(feature = "load-protocol")]
+pub trait LoadProtocol {
+    fn get_flags(&self) -> u64;
+
+    // The map itself is stored in the handoff region
+    fn set_mmap(&mut self, map: &MemoryMapInfo);
+    fn set_initrd(&mut self, base: usize, size: usize);
+    fn set_acpi_rsdp(&mut self, rsdp: usize);
+    fn set_loader_magic(&mut self);
+    // Stored NUL-terminated, fails if it doesn't fit
+    fn set_cmdline(&mut self, cmdline: &str) -> Result<(), ()>;
+
+    // Fails if the kernel requested an unknown pixel format
+    fn get_video_info(&self) -> Result<VideoInfo, ()>;
+    fn set_video_info(&mut self, info: &VideoInfo);
+    fn get_video_select(&self) -> ModeSelect;
+    // Text geometry, 0 when a graphics mode was requested
+    fn set_video_text(&mut self, columns: u32, rows: u32);
+    // Channel positions within a pixel, reported for every format
+    fn set_video_bitmask(&mut self, red: u32, green: u32, blue: u32, reserved: u32);
+    // Copy of the display's EDID, (0, 0) if there's none
+    fn set_edid(&mut self, data: usize, size: usize);
+    // Number of the display used and the address of its device path text,
+    // (VIDEO_OUTPUT_DEFAULT, 0) for the firmware's default instance
+    fn set_video_output(&mut self, number: u64, path: usize);
+
+    // Value of IA32_PAT the kernel is entered with, 0 if there's no PAT
+    fn set_pat(&mut self, pat: u64);
+    // Virtual address of the runtime services table
+    fn set_efi_runtime(&mut self, addr: usize);
+
+    // Requested kernel stack size, 0 for the default
+    fn get_stack_size(&self) -> u64;
+    fn set_stack(&mut self, base: usize, size: usize);
+
+    fn set_handoff(&mut self, base: usize, size: usize);
+    // Addresses of the symbol/string table section headers and contents
+    fn set_elf_symbols(&mut self, symtab_hdr: u64, symtab_data: u64, strtab_hdr: u64, strtab_data: u64);
+
+    // Address and number of entries of the processor table
+    fn set_cpus(&mut self, table: usize, count: usize);
+    // Address of the loader log in the handoff region
+    fn set_log(&mut self, log: usize);
+
+    fn get_alloc_requests(&mut self) -> &mut [AllocRequest];
+}
+
+#[cfg(feature = "load-protocol")]
+impl LoadProtocol for ProtoV1 {
+    fn get_flags(&self) -> u64 {
+        return self.hdr.flags;
+    }
+
+    fn set_mmap(&mut self, map: &MemoryMapInfo) {
+        self.memory_map = MemoryMapInfo {
+            address:        map.address,
+            size:           map.size,
+            entsize:        map.entsize,
+        };
+    }
+
+    fn set_initrd(&mut self, base: usize, size: usize) {
+        self.initrd_base = base as u64;
+        self.initrd_size = size as u64;
+    }
+
+    fn set_acpi_rsdp(&mut self, rsdp: usize) {
+        self.rsdp = rsdp as u64;
+    }
+
+    fn set_loader_magic(&mut self) {
+        self.hdr.loader_magic = Self::LOADER_MAGIC;
+    }
+
+    fn set_cmdline(&mut self, cmdline: &str) -> Result<(), ()> {
+        let bytes = cmdline.as_bytes();
+        if bytes.len() >= CMDLINE_SIZE {
+            return Err(());
+        }
+        self.cmdline[..bytes.len()].copy_from_slice(bytes);
+        self.cmdline[bytes.len()] = 0;
+        return Ok(());
+    }
+
+    fn get_video_info(&self) -> Result<VideoInfo, ()> {
+        return Ok(VideoInfo {
+            width:          self.video_width,
+            height:         self.video_height,
+            format:         PixelFormat::try_from(self.video_format)?,
+            framebuffer:    self.video_framebuffer,
+            pitch:          self.video_pitch,
+        });
+    }
+
+    fn set_video_info(&mut self, info: &VideoInfo) {
+        self.video_width = info.width;
+        self.video_height = info.height;
+        self.video_format = info.format as u32;
+        self.video_framebuffer = info.framebuffer;
+        self.video_pitch = info.pitch;
+    }
+
+    fn get_video_select(&self) -> ModeSelect {
+        return ModeSelect::from(self.video_select);
+    }
+
+    fn set_video_text(&mut self, columns: u32, rows: u32) {
+        self.video_columns = columns;
+        self.video_rows = rows;
+    }
+
+    fn set_video_bitmask(&mut self, red: u32, green: u32, blue: u32, reserved: u32) {
+        self.video_red_mask = red;
+        self.video_green_mask = green;
+        self.video_blue_mask = blue;
+        self.video_reserved_mask = reserved;
+    }
+
+    fn set_edid(&mut self, data: usize, size: usize) {
+        self.edid_data = data as u64;
+        self.edid_size = size as u64;
+    }
+
+    fn set_video_output(&mut self, number: u64, path: usize) {
+        self.video_output = number;
+        self.video_output_path = path as u64;
+    }
+
+    fn set_pat(&mut self, pat: u64) {
+        self.pat = pat;
+    }
+
+    fn set_efi_runtime(&mut self, addr: usize) {
+        self.efi_runtime = addr as u64;
+    }
+
+    fn get_stack_size(&self) -> u64 {
+        return self.stack_size;
+    }
+
+    fn set_stack(&mut self, base: usize, size: usize) {
+        self.stack_base = base as u64;
+        self.stack_size = size as u64;
+    }
+
+    fn set_handoff(&mut self, base: usize, size: usize) {
+        self.handoff_base = base as u64;
+        self.handoff_size = size as u64;
+    }
+
+    fn set_elf_symbols(&mut self, symtab_hdr: u64, symtab_data: u64, strtab_hdr: u64, strtab_data: u64) {
+        self.elf_symtab_hdr = symtab_hdr;
+        self.elf_symtab_data = symtab_data;
+        self.elf_strtab_hdr = strtab_hdr;
+        self.elf_strtab_data = strtab_data;
+    }
+
+    fn set_cpus(&mut self, table: usize, count: usize) {
+        self.cpu_table = table as u64;
+        self.cpu_count = count as u64;
+    }
+
+    fn set_log(&mut self, log: usize) {
+        self.log = log as u64;
+    }
+
+    fn get_alloc_requests(&mut self) -> &mut [AllocRequest] {
+        return &mut self.allocs;
+    }
+}
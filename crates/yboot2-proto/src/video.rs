@@ -0,0 +1,52 @@
+use core::convert::TryFrom;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum PixelFormat {
+    LfbRgb32    = 0,
+    LfbBgr32    = 1,
+    // Layout given by the channel masks, not necessarily 32-bit
+    LfbBitmask  = 2,
+    // Text console, no framebuffer unless the console is drawn on one
+    Text        = 0x100,
+    // Request only: any 32-bit format
+    Any         = 0xFFFFFFFF,
+}
+
+impl TryFrom<u32> for PixelFormat {
+    type Error = ();
+
+    // The field is written by the kernel, so anything can be in there
+    fn try_from(v: u32) -> Result<Self, ()> {
+        return match v {
+            0           => Ok(PixelFormat::LfbRgb32),
+            1           => Ok(PixelFormat::LfbBgr32),
+            2           => Ok(PixelFormat::LfbBitmask),
+            0x100       => Ok(PixelFormat::Text),
+            0xFFFFFFFF  => Ok(PixelFormat::Any),
+            _           => Err(())
+        };
+    }
+}
+
+// How the requested resolution is matched against available modes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ModeSelect {
+    Exact       = 0,
+    Native      = 1,
+    Largest     = 2,
+    Closest     = 3,
+}
+
+impl From<u32> for ModeSelect {
+    // Unknown values fall back to exact matching
+    fn from(v: u32) -> Self {
+        match v {
+            1 => ModeSelect::Native,
+            2 => ModeSelect::Largest,
+            3 => ModeSelect::Closest,
+            _ => ModeSelect::Exact
+        }
+    }
+}
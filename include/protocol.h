@@ -1,26 +1,167 @@
 #pragma once
 // CPU state when entering kernel:
-// Virtual memory: lower 1GiB identity mapped
-// Selectors are flat
-// TODO: CR0? (spec. if NX, PAE, PSE are enabled)
-// From UEFI specification:
-//  * CR0.EM = 0
-//  * CR0.TS = 0
+// Virtual memory:
+//  * All physical memory up to the highest address in the memory map
+//    is identity mapped, but at least the lower 4GiB. The range is
+//    extended to cover the framebuffer, rounded up to 1GiB
+//  * 1GiB pages are used when CPUID reports PDPE1GB, 2MiB otherwise
+//  * With upper mapping requested, the lower 1TiB is also mapped
+//    at 0xFFFFFF0000000000
+//  * With 5-level paging requested, CR4.LA57 is set and CR3 points to
+//    a PML5 with the same mappings. The loader refuses to boot if the
+//    CPU doesn't support LA57
+// UEFI runtime services:
+//  * If requested (requires upper mapping), EFI_MEMORY_RUNTIME regions
+//    are assigned virtual addresses in the upper mirror and
+//    SetVirtualAddressMap() is called. "efi_runtime" then holds the
+//    virtual address of EFI_RUNTIME_SERVICES, and the memory map
+//    reports the virtual addresses assigned
+// Caching:
+//  * IA32_PAT is programmed to YB_PAT_LAYOUT (see below), "pat" field
+//    reports the value or 0 if the CPU has no PAT
+//  * The framebuffer is mapped write-combining (PA4), everything else
+//    uses PA0 (WB, subject to MTRRs)
+// Registers:
+//  * RDI = physical address of this structure
+//  * RSP = top of the kernel stack (16-byte aligned), see below
+//  * RBP = 0, RFLAGS = 0x2 (interrupts disabled)
+// Control registers:
+//  * CR0 = PE | MP | ET | NE | WP | PG (EM, TS, CD, NW are clear)
+//  * CR4 = PAE | OSFXSR | OSXMMEXCPT [| LA57], everything else clear
+//  * EFER = LME | LMA [| NXE, if supported]
+// Descriptor tables:
+//  * GDT in loader-allocated memory, with flat selectors:
+//     0x08 - 64-bit code, 0x10 - data (DS, ES, FS, GS, SS),
+//     0x18 - 32-bit code
+//  * IDT is undefined, the kernel must load its own before enabling
+//    interrupts
 // Stack:
-//  * At least 120KiB available
+//  * "stack_size" bytes as requested by the kernel (rounded up to
+//    4KiB), or YB_DEFAULT_STACK_SIZE if it's 0. The loader reports the
+//    allocated range in "stack_base" and "stack_size"
+// Handoff region:
+//  * Everything passed by reference (memory map, page tables, GDT,
+//    symbol tables, EDID, display path, loader log) lives in a single
+//    region below 4GiB, reported in "handoff_base"/"handoff_size" and
+//    marked with YB_MEMORY_HANDOFF in the memory map. The kernel may
+//    reclaim it once it no longer needs the data
+// Loader allocations:
+//  * The kernel image, initrd and allocations requested in "allocs" are
+//    reported in the memory map as YB_MEMORY_KERNEL, YB_MEMORY_INITRD
+//    and YB_MEMORY_ALLOC respectively
+//  * Each "allocs" entry with non-zero "size" requests a physically
+//    contiguous region of that size, aligned to "align" (a power of two,
+//    at least 4KiB) and ending at or below "max_address" (0 - no limit).
+//    The lowest fitting address above 1MiB is returned in "base", or 0
+//    if the request couldn't be satisfied. Requests which don't fit in
+//    free memory are placed in former boot services memory
 // FPU state:
-//  * CW = 0x037F
-// MMX state:
-//  * CW = 0x1F80
-// Interrupts are disabled
+//  * FNINIT state, CW = 0x037F
+//  * MXCSR = 0x1F80
+// Processors:
+//  * "cpu_table" points to "cpu_count" struct yboot_cpu entries in the
+//    handoff region, as reported by EFI_MP_SERVICES_PROTOCOL (just the
+//    BSP if the firmware doesn't provide it)
+//  * Unless AP parking is requested, the APs are left in whatever state
+//    the firmware put them in on ExitBootServices()
+//  * With parking requested, every enabled and healthy AP is restarted and parked in a
+//    spin loop, with YB_CPU_PARKED set in its entry. The AP runs with the
+//    same page tables, GDT, PAT, CR0/CR4/EFER and FPU state as the BSP,
+//    interrupts disabled and a 4KiB stack in the handoff region.
+//    Writing "arg" and then a non-zero "entry" releases it: it jumps to
+//    "entry" with RDI = "arg". The startup code runs from a separate
+//    YB_MEMORY_HANDOFF page below 1MiB, which (as well as the handoff
+//    region) must be preserved until all parked APs are released
 
 #define YB_KERNEL_MAGIC_V1          0xA197A9B007B007UL
 #define YB_LOADER_MAGIC_V1          0x700B700B9A791AUL
 
 #define YB_CMDLINE_SIZE             256
+#define YB_DEFAULT_STACK_SIZE       0x20000
+
+// Kernel-requested features, "flags" field of the header
+#define YB_FLAG_VIDEO               (1 << 0)
+#define YB_FLAG_INITRD              (1 << 1)
+#define YB_FLAG_UPPER               (1 << 2)
+#define YB_FLAG_LA57                (1 << 3)
+#define YB_FLAG_EFI_RUNTIME         (1 << 4)
+#define YB_FLAG_SMP_PARK            (1 << 5)
+
+// Memory map types of loader-allocated regions
+#define YB_MEMORY_HANDOFF           0x80000000
+#define YB_MEMORY_KERNEL            0x80000001
+#define YB_MEMORY_INITRD            0x80000002
+#define YB_MEMORY_ALLOC             0x80000003
+
+#define YB_MAX_ALLOCS               8
+
+// PA0 = WB, PA1 = WT, PA2 = UC-, PA3 = UC,
+// PA4 = WC, PA5 = WP, PA6 = UC-, PA7 = UC
+#define YB_PAT_LAYOUT               0x0007050100070406UL
+
+#define YB_CPU_BSP                  (1 << 0)
+#define YB_CPU_ENABLED              (1 << 1)
+#define YB_CPU_HEALTHY              (1 << 2)
+#define YB_CPU_PARKED               (1 << 3)
 
 #define YB_VIDEO_FORMAT_RGB32       0
 #define YB_VIDEO_FORMAT_BGR32       1
+// Pixel layout given by the "video_*_mask" fields, which may be other
+// than 32 bits per pixel
+#define YB_VIDEO_FORMAT_BITMASK     2
+// Text console, see below
+#define YB_VIDEO_FORMAT_TEXT        0x100
+// Request only: any 32-bit format, including 32-bit BITMASK layouts
+#define YB_VIDEO_FORMAT_ANY         0xFFFFFFFF
+// Requesting any other format fails the boot
+
+// With YB_VIDEO_FORMAT_TEXT requested the display mode isn't changed:
+//  * If the console is in a real text mode, "video_format" stays TEXT,
+//    the framebuffer fields are 0 and "video_columns"/"video_rows" hold
+//    the firmware console geometry
+//  * Otherwise (the firmware console is drawn on a GOP framebuffer) the
+//    current graphics mode is reported as usual, with "video_columns"/
+//    "video_rows" giving the 8x16 cell grid of the loader's console
+// Both are 0 when a graphics mode was requested
+
+// How "video_width"/"video_height" are matched against available modes:
+//  EXACT   - exact resolution match, the loader fails otherwise
+//  NATIVE  - the display's native resolution (or the closest one), taken
+//            from the preferred timing of its EDID. Without EDID the
+//            mode the firmware has set up is assumed to be native
+//  LARGEST - the largest mode available
+//  CLOSEST - the mode closest to the requested resolution
+// Ties go to the larger mode
+#define YB_VIDEO_SELECT_EXACT       0
+#define YB_VIDEO_SELECT_NATIVE      1
+#define YB_VIDEO_SELECT_LARGEST     2
+#define YB_VIDEO_SELECT_CLOSEST     3
+
+// Display identification:
+//  * "edid_data" points to a copy of the display's EDID in the handoff
+//    region, "edid_size" bytes long (128 bytes per block, including
+//    extension blocks). The firmware's active EDID is preferred over
+//    the one it discovered. Both are 0 if no EDID is available
+//  * "video_output" is the number of the display the loader used, as
+//    listed on screen and accepted by "video_output" in yboot.cfg, and
+//    "video_output_path" points to its NUL-terminated device path text in
+//    the handoff region. If the firmware's default output was used they
+//    are YB_VIDEO_OUTPUT_DEFAULT and 0
+#define YB_VIDEO_OUTPUT_DEFAULT     0xFFFFFFFFFFFFFFFF
+
+// Loader log:
+//  * "log" points to a struct yboot_log in the handoff region with the
+//    messages the loader printed, one record per line. "data" is a ring
+//    of "size" bytes: records are at data[offset % size] for offsets from
+//    "start" up to "end", which only ever grow, and may wrap around the
+//    end of "data". The oldest records are dropped when it's full
+//  * Each record is a struct yboot_log_record followed by "length" bytes
+//    of text (not NUL-terminated), padded to a multiple of 8 bytes
+//  * Timestamps are TSC values, "tsc_per_us" gives the rate measured by
+//    the loader
+#define YB_LOG_ERROR                0
+#define YB_LOG_WARNING              1
+#define YB_LOG_INFO                 2
 
 #if !defined(__ASM__)
 #include <stdint.h>
@@ -28,20 +169,54 @@
 struct yboot_header {
     uint64_t kernel_magic;
     uint64_t loader_magic;
+    uint64_t flags;                             // R
+};
+
+struct yboot_cpu {
+    uint32_t apic_id;
+    uint32_t package;
+    uint32_t core;
+    uint32_t thread;
+    uint32_t flags;                             // YB_CPU_*
+    uint32_t __pad0;
+    uint64_t entry;                             // Written by the kernel
+    uint64_t arg;                               // Written by the kernel
+};
+
+struct yboot_log {
+    uint32_t size;
+    uint32_t tsc_per_us;
+    uint64_t start;
+    uint64_t end;
+    uint8_t data[];
+};
+
+struct yboot_log_record {
+    uint64_t timestamp;
+    uint16_t length;
+    uint8_t level;                              // YB_LOG_*
+    uint8_t __pad0[5];
+};
+
+struct yboot_alloc {
+    uint64_t size;                              // R
+    uint64_t align;                             // R
+    uint64_t max_address;                       // R
+    uint64_t base;                              // W
 };
 
 struct yboot_v1 {
     struct yboot_header header;
 
-    uint64_t memory_map_data;                   // R
-    uint32_t memory_map_size;                   // RW
+    uint64_t memory_map_data;                   // W
+    uint32_t memory_map_size;                   // W
     uint32_t memory_map_entsize;                // W
 
     // Video mode settings
     uint32_t video_width;                       // RW
     uint32_t video_height;                      // RW
     uint32_t video_format;                      // RW
-    uint32_t __pad0;                            // --
+    uint32_t video_select;                      // R
     uint64_t video_framebuffer;                 // W
     uint64_t video_pitch;                       // W
 
@@ -55,7 +230,40 @@ struct yboot_v1 {
 
     uint64_t rsdp;                              // W
 
+    // NUL-terminated command line of the selected boot entry
     char cmdline[YB_CMDLINE_SIZE];              // W
+
+    uint64_t pat;                               // W
+    uint64_t efi_runtime;                       // W
+
+    uint64_t stack_base;                        // W
+    uint64_t stack_size;                        // RW
+
+    uint64_t handoff_base;                      // W
+    uint64_t handoff_size;                      // W
+
+    uint64_t cpu_table;                         // W
+    uint64_t cpu_count;                         // W
+
+    struct yboot_alloc allocs[YB_MAX_ALLOCS];   // RW
+
+    uint32_t video_columns;                     // W
+    uint32_t video_rows;                        // W
+
+    // Channel positions within a pixel, filled in for every format.
+    // "video_pitch" is the scanline length in bytes, including padding
+    uint32_t video_red_mask;                    // W
+    uint32_t video_green_mask;                  // W
+    uint32_t video_blue_mask;                   // W
+    uint32_t video_reserved_mask;               // W
+
+    uint64_t edid_data;                         // W
+    uint64_t edid_size;                         // W
+
+    uint64_t log;                               // W
+
+    uint64_t video_output;                      // W
+    uint64_t video_output_path;                 // W
 };
 #endif
 
@@ -9,7 +9,7 @@ set -e
 
 mkdir -p image
 
-cargo build -Z build-std=core
+make PROFILE=${CONFIG}
 
 dd if=/dev/zero of=${IMAGE} bs=1M count=64
 mkfs.vfat -F32 ${IMAGE}
@@ -0,0 +1,110 @@
+// Frame-pointer backtraces, symbolised with the loader's own symbol table.
+// The table is an empty area in the image, filled in after linking by
+// tools/embed-symbols.py. It stores its own RVA, which gives the image base
+// at runtime without asking the firmware
+
+const SYMBOL_AREA_SIZE: usize = 0x10000;
+const HEADER_SIZE: usize = 16;
+const MAX_FRAMES: usize = 32;
+// Larger gaps between frames are taken as a broken chain
+const MAX_FRAME_SIZE: usize = 0x100000;
+
+// Followed by "count" (rva, name offset) pairs sorted by RVA, then the
+// NUL-terminated names. Offsets are relative to "data"
+#[repr(C)]
+struct SymbolTable {
+    magic:      [u8; 8],
+    table_rva:  u32,
+    count:      u32,
+    data:       [u8; SYMBOL_AREA_SIZE - HEADER_SIZE],
+}
+
+#[used]
+#[link_section = ".ysyms"]
+static SYMBOL_TABLE: SymbolTable = SymbolTable {
+    magic:      *b"YBSYMTAB",
+    table_rva:  0,
+    count:      0,
+    data:       [0; SYMBOL_AREA_SIZE - HEADER_SIZE],
+};
+
+impl SymbolTable {
+    fn read_u32(&self, offset: usize) -> u32 {
+        let bytes = &self.data[offset..offset + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn name(&self, offset: usize) -> &str {
+        let bytes = match self.data.get(offset..) {
+            Some(bytes) => bytes,
+            None        => return "?"
+        };
+        let length = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        core::str::from_utf8(&bytes[..length]).unwrap_or("?")
+    }
+
+    // Returns the name of the function containing `addr` and the offset into it
+    fn lookup(&self, addr: usize) -> Option<(&str, usize)> {
+        let count = core::cmp::min(self.count as usize, self.data.len() / 8);
+        if self.table_rva == 0 || count == 0 {
+            return None;
+        }
+        let base = self as *const SymbolTable as usize - self.table_rva as usize;
+        let rva = addr.checked_sub(base)?;
+
+        // Last symbol starting at or below `rva`
+        let (mut low, mut high) = (0, count);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.read_u32(mid * 8) as usize <= rva {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        let start = self.read_u32(low * 8) as usize;
+        if start > rva {
+            return None;
+        }
+        Some((self.name(self.read_u32(low * 8 + 4) as usize), rva - start))
+    }
+}
+
+fn symbol_table() -> &'static SymbolTable {
+    // The table is only filled in after the build, so the compiler must not
+    // assume it still holds the initial values
+    unsafe { &*core::ptr::read_volatile(&(&SYMBOL_TABLE as *const SymbolTable)) }
+}
+
+// Walks the RBP chain of the caller, requires the loader to be built with
+// frame pointers
+pub fn print() {
+    let rbp: u64;
+    unsafe {
+        llvm_asm!("mov %rbp, $0":"=r"(rbp):::"volatile");
+    }
+    let table = symbol_table();
+    let mut frame = rbp as usize;
+
+    errorln!("Backtrace:");
+    for _ in 0..MAX_FRAMES {
+        if frame == 0 || frame & 7 != 0 {
+            break;
+        }
+        let (next, ret) = unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+
+        match table.lookup(ret) {
+            Some((name, offset)) => errorln!("  {:#018x} {}+{:#x}", ret, name, offset),
+            None                 => errorln!("  {:#018x}", ret)
+        }
+
+        if next <= frame || next - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = next;
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{error::BootError, serial};
+use core::convert::TryFrom;
+use efi::{CStr16, File, MemoryType};
+
+// Boot options are read from \yboot.cfg on the boot partition, one
+// "key=value" per line, with "#" starting a comment. A missing file means
+// defaults for everything, unknown keys and bad values are reported and
+// skipped rather than failing the boot.
+//
+// Supported keys:
+//  mem=SIZE            Hide RAM above SIZE from the kernel
+//  memmap=SIZE$ADDR    Mark [ADDR, ADDR + SIZE) as reserved
+//  memmap=SIZE^ADDR    Mark [ADDR, ADDR + SIZE) as bad (EfiUnusableMemory).
+//                      Linux has no such form, its other memmap= forms
+//                      (e.g. "!" for persistent memory) are rejected
+//  splash=PATH         BMP or QOI image shown while loading, e.g. \splash.bmp
+//  video_output=OUTPUT Display to use: its number in the output list printed
+//                      at boot, or the start of its device path, e.g.
+//                      PciRoot(0x0)/Pci(0x1,0x0)
+//  serial=PORT         Mirror output to a 16550 UART at I/O port PORT, or
+//                      com1..com4
+//  serial_baud=BAUD    UART speed, 115200 by default
+//  timeout=SECONDS     Show the boot menu for SECONDS before booting the
+//                      default entry. With 0 (the default) it's only shown
+//                      when Shift is held at startup. "e" in the menu (or
+//                      Ctrl+E at startup) edits the command line for one boot
+//  default=N           Entry booted by default, counting from 0
+//  entry=TITLE         Starts a boot menu entry
+//  kernel=PATH         Kernel image of the entry, \kernel.elf by default
+//  initrd=PATH         Initrd of the entry, \initrd.img by default
+//  cmdline=TEXT        Kernel command line of the entry
+// kernel, initrd and cmdline before the first entry set the defaults of
+// all entries. Without any entries these defaults are booted
+// SIZE and ADDR are decimal or 0x-prefixed hex with an optional K/M/G/T suffix
+
+const CONFIG_BUFFER_SIZE: usize = 8192;
+// Architectural limit of physical addresses (MAXPHYADDR is at most 52),
+// the end of the range hidden by mem=
+const PHYS_ADDR_LIMIT: usize = 1 << 52;
+pub const MAX_MEMMAP: usize = 16;
+pub const MAX_ENTRIES: usize = 8;
+
+// Values reference the file contents, which stay in place for the
+// loader's lifetime
+static mut CONFIG_BUFFER: [u8; CONFIG_BUFFER_SIZE] = [0; CONFIG_BUFFER_SIZE];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionKind {
+    Reserved,
+    Bad,
+}
+
+// Physical range [start, end), end is exclusive as everywhere else
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub start:  usize,
+    pub end:    usize,
+    pub kind:   RegionKind,
+}
+
+impl Region {
+    // Type the range is given in the memory map
+    pub fn memory_type(&self) -> MemoryType {
+        match self.kind {
+            RegionKind::Reserved => MemoryType::Reserved,
+            RegionKind::Bad      => MemoryType::Unusable,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub title:      &'static str,
+    pub kernel:     &'static str,
+    pub initrd:     &'static str,
+    pub cmdline:    &'static str,
+}
+
+pub struct Config {
+    pub mem_limit:      Option<usize>,
+    memmap:             [Option<Region>; MAX_MEMMAP],
+    pub splash:         Option<&'static str>,
+    pub video_output:   Option<&'static str>,
+    pub serial:         Option<u16>,
+    pub serial_baud:    u32,
+    pub timeout:        u32,
+    default:            usize,
+    defaults:           Entry,
+    entries:            [Entry; MAX_ENTRIES],
+    entry_count:        usize,
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        usize::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn parse_size(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let (digits, shift) = match text.chars().last()? {
+        'k' | 'K' => (&text[..text.len() - 1], 10),
+        'm' | 'M' => (&text[..text.len() - 1], 20),
+        'g' | 'G' => (&text[..text.len() - 1], 30),
+        't' | 'T' => (&text[..text.len() - 1], 40),
+        _         => (text, 0)
+    };
+    parse_number(digits)?.checked_mul(1 << shift)
+}
+
+fn parse_region(text: &str) -> Option<Region> {
+    let (sep, kind) = match (text.find('$'), text.find('^')) {
+        (Some(pos), None) => (pos, RegionKind::Reserved),
+        (None, Some(pos)) => (pos, RegionKind::Bad),
+        _                 => return None
+    };
+    let size = parse_size(&text[..sep])?;
+    let start = parse_size(&text[sep + 1..])?;
+    if size == 0 {
+        return None;
+    }
+
+    Some(Region {
+        start,
+        end: start.checked_add(size)?,
+        kind,
+    })
+}
+
+impl Config {
+    fn new() -> Self {
+        let defaults = Entry {
+            title:      "yboot2",
+            kernel:     r"\kernel.elf",
+            initrd:     r"\initrd.img",
+            cmdline:    "",
+        };
+
+        Config {
+            mem_limit:      None,
+            memmap:         [None; MAX_MEMMAP],
+            splash:         None,
+            video_output:   None,
+            serial:         None,
+            serial_baud:    serial::DEFAULT_BAUD,
+            timeout:        0,
+            default:        0,
+            defaults,
+            entries:        [defaults; MAX_ENTRIES],
+            entry_count:    0,
+        }
+    }
+
+    pub fn load(root: &mut File, path: &CStr16) -> Result<Self, BootError> {
+        let mut config = Config::new();
+
+        let mut file = match root.open(path, efi::proto::fp::OPEN_MODE_READ, 0) {
+            Ok(file) => file,
+            Err(_)   => return Ok(config)
+        };
+
+        let buffer = unsafe { &mut CONFIG_BUFFER };
+        let size = file.read(buffer).map_err(BootError::FileError)?;
+        if size == CONFIG_BUFFER_SIZE {
+            warnln!("yboot.cfg: file is too large, the rest is ignored");
+        }
+
+        match core::str::from_utf8(&buffer[..size]) {
+            Ok(text) => config.parse(text),
+            Err(_)   => warnln!("yboot.cfg: not valid UTF-8, ignored")
+        }
+
+        Ok(config)
+    }
+
+    fn parse(&mut self, text: &'static str) {
+        for (num, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None      => line
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+                None      => {
+                    warnln!("yboot.cfg:{}: expected key=value", num + 1);
+                    continue;
+                }
+            };
+
+            if !self.set(key, value) {
+                warnln!("yboot.cfg:{}: bad option \"{}\"", num + 1, line);
+            }
+        }
+    }
+
+    // Returns false if the key is unknown or the value is malformed
+    fn set(&mut self, key: &'static str, value: &'static str) -> bool {
+        match key {
+            "mem" => {
+                self.mem_limit = parse_size(value);
+                self.mem_limit.is_some()
+            }
+            "memmap" => {
+                let region = match parse_region(value) {
+                    Some(region) => region,
+                    None         => return false
+                };
+                match self.memmap.iter_mut().find(|slot| slot.is_none()) {
+                    Some(slot) => {
+                        *slot = Some(region);
+                        true
+                    }
+                    None => false
+                }
+            }
+            "splash" => {
+                self.splash = Some(value);
+                !value.is_empty()
+            }
+            "video_output" => {
+                self.video_output = Some(value);
+                !value.is_empty()
+            }
+            "serial" => {
+                self.serial = serial::port_by_name(value)
+                    .or_else(|| parse_number(value).and_then(|port| u16::try_from(port).ok()));
+                self.serial.is_some()
+            }
+            "timeout" => match value.parse() {
+                Ok(timeout) => {
+                    self.timeout = timeout;
+                    true
+                }
+                Err(_) => false
+            },
+            "default" => match value.parse() {
+                Ok(default) => {
+                    self.default = default;
+                    true
+                }
+                Err(_) => false
+            },
+            "entry" => {
+                if self.entry_count == MAX_ENTRIES || value.is_empty() {
+                    return false;
+                }
+                self.entries[self.entry_count] = Entry {
+                    title: value,
+                    ..self.defaults
+                };
+                self.entry_count += 1;
+                true
+            }
+            "kernel" | "initrd" | "cmdline" => {
+                let entry = self.current_entry();
+                match key {
+                    "kernel" => entry.kernel = value,
+                    "initrd" => entry.initrd = value,
+                    _        => entry.cmdline = value,
+                }
+                key == "cmdline" || !value.is_empty()
+            }
+            "serial_baud" => match value.parse() {
+                Ok(baud) if serial::is_valid_baud(baud) => {
+                    self.serial_baud = baud;
+                    true
+                }
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    // Entry the kernel/initrd/cmdline keys apply to
+    fn current_entry(&mut self) -> &mut Entry {
+        match self.entry_count {
+            0     => &mut self.defaults,
+            count => &mut self.entries[count - 1]
+        }
+    }
+
+    // Boot menu entries, there's always at least one
+    pub fn entries(&self) -> &[Entry] {
+        match self.entry_count {
+            0     => core::slice::from_ref(&self.defaults),
+            count => &self.entries[..count]
+        }
+    }
+
+    // The configured default, or the first entry if it's out of range
+    pub fn default_entry(&self) -> usize {
+        if self.default < self.entries().len() {
+            self.default
+        } else {
+            0
+        }
+    }
+
+    pub fn memmap(&self) -> impl Iterator<Item = &Region> {
+        self.memmap.iter().flatten()
+    }
+
+    // Ranges hidden from the kernel: memmap= entries and everything
+    // above the mem= limit
+    pub fn exclusions(&self) -> impl Iterator<Item = Region> + '_ {
+        let limit = self.mem_limit.filter(|start| *start < PHYS_ADDR_LIMIT).map(|start| Region {
+            start,
+            end:    PHYS_ADDR_LIMIT,
+            kind:   RegionKind::Reserved,
+        });
+        self.memmap().copied().chain(limit)
+    }
+
+    // Upper bound of descriptors added to a memory map by applying the
+    // limits, each range may split descriptors at both ends
+    pub fn mmap_slack(&self) -> usize {
+        self.memmap().count() * 2 + self.mem_limit.is_some() as usize
+    }
+}
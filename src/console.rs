@@ -0,0 +1,158 @@
+use crate::font;
+use efi::{gop::PixelBitmask, system_table};
+use core::fmt;
+use yboot2_proto::VideoInfo;
+
+// Text console drawn directly on the framebuffer. Once attached after a
+// mode switch it receives all println!() output, which keeps it readable
+// regardless of what the firmware console does, and also after
+// ExitBootServices(). If the firmware console draws on the same
+// framebuffer it's no longer written to, as the two would garble each
+// other's output
+
+struct Console {
+    base:       usize,
+    pitch:      usize,
+    bpp:        usize,
+    foreground: u32,
+    columns:    usize,
+    rows:       usize,
+    column:     usize,
+    row:        usize,
+    shared:     bool,
+}
+
+static mut CONSOLE: Option<Console> = None;
+
+impl Console {
+    fn put_pixel(&self, x: usize, y: usize, color: u32) {
+        let offset = self.base + y * self.pitch + x * self.bpp;
+        unsafe {
+            match self.bpp {
+                4 => core::ptr::write_volatile(offset as *mut u32, color),
+                2 => core::ptr::write_volatile(offset as *mut u16, color as u16),
+                _ => {
+                    for i in 0..self.bpp {
+                        core::ptr::write_volatile((offset + i) as *mut u8, (color >> (i * 8)) as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_glyph(&self, c: char) {
+        let x = self.column * font::WIDTH as usize;
+        let y = self.row * font::HEIGHT as usize;
+
+        for (dy, bits) in font::glyph(c).iter().enumerate() {
+            for dx in 0..font::WIDTH as usize {
+                let color = if bits & (0x80 >> dx) != 0 { self.foreground } else { 0 };
+                self.put_pixel(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    fn clear_rows(&self, first: usize, count: usize) {
+        let line = font::HEIGHT as usize * self.pitch;
+        unsafe {
+            core::ptr::write_bytes((self.base + first * line) as *mut u8, 0, count * line);
+        }
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+
+        // Scroll everything up by one line
+        let line = font::HEIGHT as usize * self.pitch;
+        unsafe {
+            core::ptr::copy((self.base + line) as *const u8,
+                            self.base as *mut u8,
+                            (self.rows - 1) * line);
+        }
+        self.clear_rows(self.rows - 1, 1);
+    }
+
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\r' => self.column = 0,
+            '\n' => self.newline(),
+            _    => {
+                if self.column == self.columns {
+                    self.newline();
+                }
+                self.draw_glyph(c);
+                self.column += 1;
+            }
+        }
+    }
+}
+
+// Whether the firmware console draws on `framebuffer`. A console split
+// across several displays has no framebuffer of its own, it's assumed to
+// include this one
+fn firmware_console_on(framebuffer: usize) -> bool {
+    match system_table().con_out_gop() {
+        Ok(gop) => {
+            let base = gop.current_mode().framebuffer_addr();
+            base == 0 || base == framebuffer
+        }
+        Err(_) => false
+    }
+}
+
+// Starts drawing loader output on the framebuffer described by `video`,
+// at the top of the screen. The screen is cleared if `clear` is set
+pub fn attach(video: &VideoInfo, mask: &PixelBitmask, clear: bool) {
+    let all = mask.red_mask | mask.green_mask | mask.blue_mask | mask.reserved_mask;
+    let bpp = ((32 - all.leading_zeros() + 7) / 8) as usize;
+    let columns = (video.width / font::WIDTH) as usize;
+    let rows = (video.height / font::HEIGHT) as usize;
+
+    if video.framebuffer == 0 || bpp == 0 || columns == 0 || rows == 0 {
+        return;
+    }
+
+    let console = Console {
+        base:       video.framebuffer as usize,
+        pitch:      video.pitch as usize,
+        bpp,
+        foreground: mask.red_mask | mask.green_mask | mask.blue_mask,
+        columns,
+        rows,
+        column:     0,
+        row:        0,
+        shared:     firmware_console_on(video.framebuffer as usize),
+    };
+    if clear {
+        console.clear_rows(0, rows);
+    }
+
+    unsafe {
+        CONSOLE = Some(console);
+    }
+}
+
+pub fn is_attached() -> bool {
+    unsafe { CONSOLE.is_some() }
+}
+
+// True if the console is attached to the framebuffer the firmware console
+// draws on
+pub fn replaces_con_out() -> bool {
+    unsafe { CONSOLE.as_ref().map_or(false, |console| console.shared) }
+}
+
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(console) = unsafe { CONSOLE.as_mut() } {
+            s.chars().for_each(|c| console.put_char(c));
+        }
+        Ok(())
+    }
+}
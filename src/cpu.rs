@@ -0,0 +1,103 @@
+use core::arch::x86_64::{CpuidResult, __cpuid_count, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+use efi::BootServices;
+
+// TSC ticks per microsecond, set by calibrate_tsc()
+static TSC_PER_US: AtomicU64 = AtomicU64::new(0);
+
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    unsafe { __cpuid_count(leaf, subleaf) }
+}
+
+fn max_basic_leaf() -> u32 {
+    cpuid(0, 0).eax
+}
+
+fn max_extended_leaf() -> u32 {
+    cpuid(0x80000000, 0).eax
+}
+
+// CPUID.80000001h:EDX.Page1GB[bit 26]
+pub fn has_1gib_pages() -> bool {
+    max_extended_leaf() >= 0x80000001 && (cpuid(0x80000001, 0).edx & (1 << 26)) != 0
+}
+
+// CPUID.80000001h:EDX.NX[bit 20]
+pub fn has_nx() -> bool {
+    max_extended_leaf() >= 0x80000001 && (cpuid(0x80000001, 0).edx & (1 << 20)) != 0
+}
+
+// CPUID.(EAX=07H,ECX=0):ECX.LA57[bit 16]
+pub fn has_la57() -> bool {
+    max_basic_leaf() >= 7 && (cpuid(7, 0).ecx & (1 << 16)) != 0
+}
+
+// CPUID.01H:EDX.PAT[bit 16]
+pub fn has_pat() -> bool {
+    (cpuid(1, 0).edx & (1 << 16)) != 0
+}
+
+// Initial APIC ID of the current CPU, x2APIC ID if leaf 0Bh is present
+pub fn apic_id() -> u32 {
+    if max_basic_leaf() >= 0xB && cpuid(0xB, 0).ebx != 0 {
+        cpuid(0xB, 0).edx
+    } else {
+        cpuid(1, 0).ebx >> 24
+    }
+}
+
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let lo: u32;
+    let hi: u32;
+    llvm_asm!("rdmsr":"={eax}"(lo), "={edx}"(hi):"{ecx}"(msr)::"volatile");
+    ((hi as u64) << 32) | lo as u64
+}
+
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    llvm_asm!("wrmsr"::"{ecx}"(msr), "{eax}"(value as u32), "{edx}"((value >> 32) as u32):"memory":"volatile");
+}
+
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    llvm_asm!("inb %dx, %al":"={al}"(value):"{dx}"(port)::"volatile");
+    value
+}
+
+pub unsafe fn outb(port: u16, value: u8) {
+    llvm_asm!("outb %al, %dx"::"{dx}"(port), "{al}"(value)::"volatile");
+}
+
+// Stops the CPU for good
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            llvm_asm!("cli; hlt"::::"volatile");
+        }
+    }
+}
+
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// Measures the TSC rate against Stall(), so delay_us() can be used
+// after boot services are terminated
+pub fn calibrate_tsc(bs: &BootServices) {
+    let start = rdtsc();
+    bs.stall(1000);
+    let ticks = (rdtsc() - start) / 1000;
+    TSC_PER_US.store(core::cmp::max(ticks, 1), Ordering::Relaxed);
+}
+
+// 0 until calibrate_tsc() is called
+pub fn tsc_per_us() -> u64 {
+    TSC_PER_US.load(Ordering::Relaxed)
+}
+
+pub fn delay_us(us: u64) {
+    let start = rdtsc();
+    let ticks = us * TSC_PER_US.load(Ordering::Relaxed);
+    while rdtsc() - start < ticks {
+        core::hint::spin_loop();
+    }
+}
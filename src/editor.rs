@@ -0,0 +1,148 @@
+use core::fmt::Write;
+use efi::{
+    stip::{SCAN_DELETE, SCAN_END, SCAN_ESC, SCAN_HOME, SCAN_LEFT, SCAN_RIGHT},
+    stop::Color,
+    system_table,
+};
+
+// Single-line text editor on the firmware console, used to change the
+// kernel command line before booting. Long lines scroll horizontally
+
+// Fits struct yboot_v1's "cmdline" with the terminating NUL
+const MAX_BYTES: usize = 255;
+
+// Result of editing, UTF-8 encoded
+pub struct Line {
+    buf:    [u8; MAX_BYTES],
+    len:    usize,
+}
+
+impl Line {
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+pub struct LineEditor {
+    text:       [char; MAX_BYTES],
+    length:     usize,
+    bytes:      usize,
+    cursor:     usize,
+    scroll:     usize,
+    column:     usize,
+    row:        usize,
+    width:      usize,
+}
+
+impl LineEditor {
+    // The line occupies `width` cells starting at (`column`, `row`)
+    pub fn new(initial: &str, column: usize, row: usize, width: usize) -> Self {
+        let mut editor = LineEditor {
+            text:       ['\0'; MAX_BYTES],
+            length:     0,
+            bytes:      0,
+            cursor:     0,
+            scroll:     0,
+            column,
+            row,
+            width:      core::cmp::max(width, 2),
+        };
+        for c in initial.chars() {
+            editor.insert(c);
+        }
+        editor.cursor = editor.length;
+        editor
+    }
+
+    fn insert(&mut self, c: char) {
+        if self.bytes + c.len_utf8() > MAX_BYTES {
+            return;
+        }
+        self.text.copy_within(self.cursor..self.length, self.cursor + 1);
+        self.text[self.cursor] = c;
+        self.length += 1;
+        self.bytes += c.len_utf8();
+        self.cursor += 1;
+    }
+
+    fn remove(&mut self, pos: usize) {
+        if pos >= self.length {
+            return;
+        }
+        self.bytes -= self.text[pos].len_utf8();
+        self.text.copy_within(pos + 1..self.length, pos);
+        self.length -= 1;
+    }
+
+    fn draw(&mut self) {
+        // Keep the cursor in view, the last cell is left for it at the end
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + self.width {
+            self.scroll = self.cursor + 1 - self.width;
+        }
+
+        let out = &mut system_table().con_out;
+        out.set_cursor_position(self.column, self.row).ok();
+        for pos in self.scroll..self.scroll + self.width {
+            let c = if pos < self.length { self.text[pos] } else { ' ' };
+            write!(out, "{}", c).ok();
+        }
+        out.set_cursor_position(self.column + self.cursor - self.scroll, self.row).ok();
+    }
+
+    // Returns the edited line, or None if editing was cancelled with Esc.
+    // Console errors also cancel editing, after telling the user
+    pub fn run(mut self) -> Option<Line> {
+        let out = &mut system_table().con_out;
+        out.set_color(Color::White, Color::Black).ok();
+        out.enable_cursor(true).ok();
+
+        loop {
+            self.draw();
+
+            let key = match system_table().con_in.read_key_blocking() {
+                Ok(key) => key,
+                Err(err) => {
+                    // The menu is redrawn afterwards, leave time to read it
+                    out.set_cursor_position(self.column, self.row + 1).ok();
+                    warnln!("Failed to read a key, editing cancelled: {}", err);
+                    system_table().boot_services.stall(2000000);
+                    return None;
+                }
+            };
+            match (key.scan_code, key.unicode_char) {
+                (SCAN_ESC, _) => return None,
+                (SCAN_LEFT, _) => self.cursor = self.cursor.saturating_sub(1),
+                (SCAN_RIGHT, _) => self.cursor = core::cmp::min(self.cursor + 1, self.length),
+                (SCAN_HOME, _) => self.cursor = 0,
+                (SCAN_END, _) => self.cursor = self.length,
+                (SCAN_DELETE, _) => self.remove(self.cursor),
+                // Backspace
+                (_, 0x08) => {
+                    if self.cursor > 0 {
+                        self.cursor -= 1;
+                        self.remove(self.cursor);
+                    }
+                }
+                (_, 0x0D) => break,
+                (_, unit) if unit >= 0x20 => {
+                    // Surrogates can't come on their own from the console
+                    if let Some(c) = core::char::from_u32(unit as u32) {
+                        self.insert(c);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut line = Line {
+            buf:    [0; MAX_BYTES],
+            len:    0,
+        };
+        for c in self.text[..self.length].iter() {
+            line.len += c.encode_utf8(&mut line.buf[line.len..]).len();
+        }
+        Some(line)
+    }
+}
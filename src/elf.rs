@@ -1,4 +1,5 @@
 use crate::error::ImageLoadError;
+use crate::handoff::Handoff;
 use core::mem::{size_of, MaybeUninit};
 use efi::{CStr16, File, MemoryMap};
 use yboot2_proto::{LoadProtocol, Magic};
@@ -12,6 +13,7 @@ type XWord = u64;
 const PT_LOAD: Word = 1;
 
 const SHT_PROGBITS: Word = 1;
+const SHT_SYMTAB: Word = 2;
 
 const SHF_WRITE: XWord = 1 << 0;
 const SHF_ALLOC: XWord = 1 << 1;
@@ -35,6 +37,7 @@ struct Ehdr {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct Shdr {
     name: Word,
     _type: Word,
@@ -60,6 +63,27 @@ struct Phdr {
     align: XWord,
 }
 
+// Section headers of the kernel's symbol table and its string table
+pub struct Symbols {
+    symtab: Shdr,
+    strtab: Shdr,
+}
+
+// Physical addresses of the tables copied to the handoff region
+pub struct LoadedSymbols {
+    pub symtab_hdr: u64,
+    pub symtab_data: u64,
+    pub strtab_hdr: u64,
+    pub strtab_data: u64,
+}
+
+impl Symbols {
+    // Space required in the handoff region, including alignment
+    pub fn size(&self) -> usize {
+        2 * (size_of::<Shdr>() + 15) + self.symtab.size as usize + self.strtab.size as usize + 30
+    }
+}
+
 pub struct Object {
     file: File,
     ehdr: Ehdr,
@@ -112,7 +136,7 @@ impl Object {
             .map_err(ImageLoadError::IOError)?
             != size_of::<Phdr>()
         {
-            Err(ImageLoadError::IOError(efi::Status::Err))
+            Err(ImageLoadError::IOError(efi::Status::EndOfFile))
         } else {
             Ok(())
         }
@@ -126,12 +150,60 @@ impl Object {
             .map_err(ImageLoadError::IOError)?
             != size_of::<Shdr>()
         {
-            Err(ImageLoadError::IOError(efi::Status::Err))
+            Err(ImageLoadError::IOError(efi::Status::EndOfFile))
         } else {
             Ok(())
         }
     }
 
+    pub fn locate_symbols(&mut self) -> Result<Option<Symbols>, ImageLoadError> {
+        let mut shdr = unsafe { MaybeUninit::<Shdr>::uninit().assume_init() };
+
+        for i in 0..self.ehdr.shnum {
+            self.read_shdr(&mut shdr, i as usize)?;
+
+            if shdr._type == SHT_SYMTAB {
+                let mut strtab = unsafe { MaybeUninit::<Shdr>::uninit().assume_init() };
+                self.read_shdr(&mut strtab, shdr.link as usize)?;
+
+                return Ok(Some(Symbols {
+                    symtab: shdr,
+                    strtab,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Copies section's header and contents, returns their addresses
+    fn load_section(&mut self, shdr: &Shdr, handoff: &Handoff) -> Result<(u64, u64), ImageLoadError> {
+        let hdr = handoff.alloc(size_of::<Shdr>(), 16);
+        unsafe {
+            core::ptr::write(hdr as *mut Shdr, *shdr);
+        }
+
+        let data = handoff.alloc_slice(shdr.size as usize);
+        self.file.seek(shdr.offset).map_err(ImageLoadError::IOError)?;
+        if self.file.read(data).map_err(ImageLoadError::IOError)? != data.len() {
+            return Err(ImageLoadError::IOError(efi::Status::EndOfFile));
+        }
+
+        Ok((hdr as u64, data.as_ptr() as u64))
+    }
+
+    pub fn load_symbols(&mut self, syms: &Symbols, handoff: &Handoff) -> Result<LoadedSymbols, ImageLoadError> {
+        let (symtab_hdr, symtab_data) = self.load_section(&syms.symtab, handoff)?;
+        let (strtab_hdr, strtab_data) = self.load_section(&syms.strtab, handoff)?;
+
+        Ok(LoadedSymbols {
+            symtab_hdr,
+            symtab_data,
+            strtab_hdr,
+            strtab_data,
+        })
+    }
+
     // Called after load()
     pub fn locate_protocol_data<T: Magic + LoadProtocol>(
         &mut self,
@@ -158,15 +230,12 @@ impl Object {
         Err(ImageLoadError::NoProtocol)
     }
 
-    pub fn load(&mut self, mmap: &MemoryMap) -> Result<usize, ImageLoadError> {
-        extern "C" {
-            fn memset(block: *mut u8, value: i32, count: usize) -> *mut u8;
-        }
-
+    // Checks that all pages in load segments are usable according to
+    // `mmap`, which must be up to date. Also finds out the kernel's lowest
+    // and highest physical addresses, to be claimed before load()
+    pub fn check_segments(&mut self, mmap: &MemoryMap) -> Result<(), ImageLoadError> {
         let mut phdr = unsafe { MaybeUninit::<Phdr>::uninit().assume_init() };
 
-        // 1. Check that all pages in load segments are usable
-        //    Also find out kernel's lowest and highest physical addresses
         for i in 0..self.ehdr.phnum {
             self.read_phdr(&mut phdr, i as usize)?;
 
@@ -189,15 +258,24 @@ impl Object {
                     self.end = end as usize;
                 }
 
-                for addr in (start..end).step_by(0x1000) {
-                    if !mmap.is_usable_now(addr as usize) {
-                        return Err(ImageLoadError::BadSegment(start, end, addr));
-                    }
+                if let Some(addr) = mmap.first_unusable(start as usize, end as usize) {
+                    return Err(ImageLoadError::BadSegment(start, end, addr as u64));
                 }
             }
         }
 
-        // 2. Load segments
+        Ok(())
+    }
+
+    // Loads the segments, returns the entry point. The range found by
+    // check_segments() must be claimed first
+    pub fn load(&mut self) -> Result<usize, ImageLoadError> {
+        extern "C" {
+            fn memset(block: *mut u8, value: i32, count: usize) -> *mut u8;
+        }
+
+        let mut phdr = unsafe { MaybeUninit::<Phdr>::uninit().assume_init() };
+
         for i in 0..self.ehdr.phnum {
             self.read_phdr(&mut phdr, i as usize)?;
 
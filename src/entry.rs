@@ -0,0 +1,235 @@
+use crate::cpu;
+use crate::error::BootError;
+use crate::handoff::Handoff;
+use crate::mem::PageTables;
+use efi::{boot::AllocateType, BootServices, MemoryType};
+
+// Every kernel entry goes through this trampoline, which loads the
+// loader's GDT and brings the CPU to the state documented in protocol.h.
+// CR4.LA57 can't be toggled while long mode is active (and the firmware
+// may run with either paging mode), so the new page tables are activated
+// via a trip through 32-bit protected mode with paging disabled. The code
+// is position-independent and is copied to a page of the handoff region
+// (below 4GiB) before running, which also keeps the GDT alive for the kernel.
+//
+// Upon entry:
+//  %rdi - value passed to the kernel in %rdi
+//  %rsi - kernel entry point
+//  %rdx - root page table address, below 4GiB
+//  %rcx - address of the trampoline copy, below 4GiB
+//  %r8  - kernel stack top
+//  %r9  - CR4 value
+//  %r10 - EFER value
+global_asm!(r#"
+    .section .text
+    .code64
+    .global entry_trampoline_start
+    .global entry_trampoline_end
+    .global entry_trampoline_gdt
+entry_trampoline_start:
+    cli
+    cld
+
+    // Upper halves of the registers don't survive compatibility mode
+    mov %rdi, (.Lentry_arg - entry_trampoline_start)(%rcx)
+    mov %rsi, (.Lentry_addr - entry_trampoline_start)(%rcx)
+    mov %r8, (.Lentry_rsp - entry_trampoline_start)(%rcx)
+    mov %r9d, (.Lentry_cr4 - entry_trampoline_start)(%rcx)
+    mov %r10d, (.Lentry_efer - entry_trampoline_start)(%rcx)
+
+    // Relocate and load the GDT
+    lea (.Lentry_gdt - entry_trampoline_start)(%rcx), %rax
+    mov %rax, (.Lentry_gdtr + 2 - entry_trampoline_start)(%rcx)
+    lgdt (.Lentry_gdtr - entry_trampoline_start)(%rcx)
+
+    // Paging can't be disabled with PCIDE set
+    mov %cr4, %rax
+    and $~(1 << 17), %rax
+    mov %rax, %cr4
+
+    // The firmware's stack may be above 4GiB
+    lea (.Lentry_stack - entry_trampoline_start)(%rcx), %rsp
+
+    // Drop to compatibility mode
+    lea (.Lentry_compat - entry_trampoline_start)(%rcx), %rax
+    pushq $0x18
+    pushq %rax
+    lretq
+
+    .code32
+.Lentry_compat:
+    mov $0x10, %eax
+    mov %eax, %ds
+    mov %eax, %es
+    mov %eax, %ss
+
+    // Disable paging, this also deactivates long mode
+    mov %cr0, %eax
+    and $0x7FFFFFFF, %eax
+    mov %eax, %cr0
+
+    mov (.Lentry_cr4 - entry_trampoline_start)(%ecx), %eax
+    mov %eax, %cr4
+    mov %edx, %cr3
+
+    mov %ecx, %esi
+    mov $0xC0000080, %ecx
+    mov (.Lentry_efer - entry_trampoline_start)(%esi), %eax
+    xor %edx, %edx
+    wrmsr
+    mov %esi, %ecx
+
+    // PE | MP | ET | NE | WP | PG, EFER.LME is set
+    mov $0x80010033, %eax
+    mov %eax, %cr0
+
+    // Back to 64-bit mode
+    lea (.Lentry_long - entry_trampoline_start)(%ecx), %eax
+    pushl $0x08
+    pushl %eax
+    lretl
+
+    .code64
+.Lentry_long:
+    mov $0x10, %eax
+    mov %eax, %ds
+    mov %eax, %es
+    mov %eax, %fs
+    mov %eax, %gs
+    mov %eax, %ss
+
+    mov %ecx, %ecx
+    mov (.Lentry_arg - entry_trampoline_start)(%rcx), %rdi
+    mov (.Lentry_addr - entry_trampoline_start)(%rcx), %rsi
+    mov (.Lentry_rsp - entry_trampoline_start)(%rcx), %rsp
+
+    fninit
+    ldmxcsr (.Lentry_mxcsr - entry_trampoline_start)(%rcx)
+
+    // Clears IF, DF and the rest
+    pushq $0x2
+    popfq
+
+    xor %rbp, %rbp
+    jmp *%rsi
+
+    .p2align 3
+entry_trampoline_gdt:
+.Lentry_gdt:
+    .quad 0                     // 0x00: null
+    .quad 0x00AF9A000000FFFF    // 0x08: 64-bit code
+    .quad 0x00CF92000000FFFF    // 0x10: data
+    .quad 0x00CF9A000000FFFF    // 0x18: 32-bit code
+.Lentry_gdt_end:
+.Lentry_gdtr:
+    .word .Lentry_gdt_end - .Lentry_gdt - 1
+    .quad 0
+.Lentry_arg:
+    .quad 0
+.Lentry_addr:
+    .quad 0
+.Lentry_rsp:
+    .quad 0
+.Lentry_cr4:
+    .long 0
+.Lentry_efer:
+    .long 0
+.Lentry_mxcsr:
+    .long 0x1F80
+
+    .p2align 4
+    .skip 256
+.Lentry_stack:
+entry_trampoline_end:
+"#);
+
+extern "C" {
+    static entry_trampoline_start: u8;
+    static entry_trampoline_end: u8;
+    static entry_trampoline_gdt: u8;
+}
+
+const CR4_PAE: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_LA57: u64 = 1 << 12;
+
+const EFER_LME: u64 = 1 << 8;
+const EFER_NXE: u64 = 1 << 11;
+
+// Used when the kernel doesn't specify the stack size, matches the
+// 128KiB UEFI guarantees to applications
+pub const DEFAULT_STACK_SIZE: usize = 128 * 1024;
+
+const GDT_SIZE: usize = 4 * 8;
+
+// CR4 value the kernel (and parked APs) run with
+pub fn kernel_cr4(tables: &PageTables) -> u64 {
+    let mut cr4 = CR4_PAE | CR4_OSFXSR | CR4_OSXMMEXCPT;
+    if tables.is_5level() {
+        cr4 |= CR4_LA57;
+    }
+    cr4
+}
+
+pub fn kernel_efer() -> u64 {
+    let mut efer = EFER_LME;
+    if cpu::has_nx() {
+        efer |= EFER_NXE;
+    }
+    efer
+}
+
+pub struct Trampoline {
+    base: usize,
+}
+
+impl Trampoline {
+    pub const SIZE: usize = 0x1000;
+
+    pub fn new(handoff: &Handoff) -> Self {
+        let base = handoff.alloc_pages(Self::SIZE / 0x1000);
+
+        unsafe {
+            let start = &entry_trampoline_start as *const u8;
+            let size = &entry_trampoline_end as *const u8 as usize - start as usize;
+            assert!(size <= Self::SIZE);
+            core::ptr::copy_nonoverlapping(start, base as *mut u8, size);
+        }
+
+        Trampoline { base }
+    }
+
+    // Address and limit of the loader GDT copy, which stays valid
+    // for as long as the handoff region does
+    pub fn gdt(&self) -> (usize, u16) {
+        let offset = unsafe {
+            &entry_trampoline_gdt as *const u8 as usize - &entry_trampoline_start as *const u8 as usize
+        };
+        (self.base + offset, (GDT_SIZE - 1) as u16)
+    }
+
+    // Activates the page tables and jumps to the kernel with %rdi = arg
+    pub fn enter_kernel(&self, tables: &PageTables, entry: usize, arg: usize, stack_top: usize) -> ! {
+        let cr4 = kernel_cr4(tables);
+        let efer = kernel_efer();
+
+        unsafe {
+            llvm_asm!("jmp *%rax"::"{rax}"(self.base),
+                                   "{rdi}"(arg),
+                                   "{rsi}"(entry),
+                                   "{rdx}"(tables.root()),
+                                   "{rcx}"(self.base),
+                                   "{r8}"(stack_top & !0xF),
+                                   "{r9}"(cr4),
+                                   "{r10}"(efer));
+        }
+        loop {}
+    }
+}
+
+// Returns the base of the allocated stack
+pub fn allocate_stack(bs: &BootServices, size: usize) -> Result<usize, BootError> {
+    bs.allocate_pages(AllocateType::AnyPages, MemoryType::LoaderData, (size + 0xFFF) / 0x1000, 0)
+        .map_err(BootError::MemoryAllocationError)
+}
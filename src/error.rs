@@ -7,8 +7,14 @@ pub enum BootError {
     ImageLoadError(ImageLoadError),
     InitrdLoadError(InitrdLoadError),
     MemoryMapError(efi::Status),
+    MemoryAllocationError(efi::Status),
     FileError(efi::Status),
     TerminateServicesError(efi::Status),
+    La57Unsupported,
+    RuntimeWithoutUpper,
+    RuntimeMappingError(efi::Status),
+    ProcessorInfoError(efi::Status),
+    BadSplashImage,
     VideoModeUnsupported,
     VideoModeFailed,
 }
@@ -42,8 +48,23 @@ impl From<ImageLoadError> for BootError {
 }
 
 impl From<&BootError> for efi::Status {
-    fn from(_: &BootError) -> Self {
-        efi::Status::Err
+    fn from(e: &BootError) -> Self {
+        use BootError::*;
+        match e {
+            ImageLoadError(self::ImageLoadError::IOError(status)) => *status,
+            ImageLoadError(_) => efi::Status::LoadError,
+            InitrdLoadError(self::InitrdLoadError::IOError(status)) => *status,
+            InitrdLoadError(self::InitrdLoadError::NoSpace) => efi::Status::OutOfResources,
+            MemoryMapError(status)
+            | MemoryAllocationError(status)
+            | FileError(status)
+            | TerminateServicesError(status)
+            | RuntimeMappingError(status)
+            | ProcessorInfoError(status) => *status,
+            La57Unsupported | RuntimeWithoutUpper | VideoModeUnsupported => efi::Status::Unsupported,
+            BadSplashImage => efi::Status::LoadError,
+            VideoModeFailed => efi::Status::DeviceError,
+        }
     }
 }
 
@@ -53,10 +74,17 @@ impl fmt::Display for BootError {
         match self {
             ImageLoadError(e) => e.fmt(f),
             InitrdLoadError(e) => e.fmt(f),
-            _ => {
-                write!(f, "Unknown error: {:?}", self)?;
-                Ok(())
-            }
+            La57Unsupported => write!(f, "The kernel requested 5-level paging, but the CPU doesn't support LA57"),
+            RuntimeWithoutUpper => write!(f, "Virtual runtime services require the upper mapping"),
+            MemoryMapError(e) => write!(f, "Failed to get the memory map: {}", e),
+            MemoryAllocationError(e) => write!(f, "Failed to allocate memory: {}", e),
+            FileError(e) => write!(f, "File error: {}", e),
+            TerminateServicesError(e) => write!(f, "Failed to exit boot services: {}", e),
+            RuntimeMappingError(e) => write!(f, "Failed to set runtime services virtual address map: {}", e),
+            ProcessorInfoError(e) => write!(f, "Failed to query processor information: {}", e),
+            BadSplashImage => write!(f, "Unsupported or corrupt splash image"),
+            VideoModeUnsupported => write!(f, "No suitable video mode"),
+            VideoModeFailed => write!(f, "Failed to set the video mode"),
         }
     }
 }
@@ -65,7 +93,7 @@ impl fmt::Display for InitrdLoadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use InitrdLoadError::*;
         match self {
-            IOError(e) => write!(f, "I/O or file error (initrd): {:?}", e),
+            IOError(e) => write!(f, "I/O or file error (initrd): {}", e),
             NoSpace => write!(f, "Failed to fit initrd in memory"),
         }
     }
@@ -85,7 +113,7 @@ impl fmt::Display for ImageLoadError {
                 "Invalid segment range: 0x{:016x} .. 0x{:016x}. Page 0x{:016x} can't be used.",
                 start, end, page
             ),
-            IOError(e) => write!(f, "I/O or file error (image): {:?}", e),
+            IOError(e) => write!(f, "I/O or file error (image): {}", e),
             NoProtocol => write!(f, "The image doesn't have a protocol structure"),
             BadTarget => write!(f, "The image targets a different arch"),
             BadMagic => write!(f, "Bad image magic"),
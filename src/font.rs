@@ -0,0 +1,20 @@
+// Built-in 8x16 font of the framebuffer console, a PSF2 file generated
+// by tools/mkfont.py. Covers ASCII, glyph 0 is drawn for anything else
+
+static FONT: &[u8] = include_bytes!("font.psf");
+
+const HEADER_SIZE: usize = 32;
+const GLYPH_COUNT: usize = 128;
+const GLYPH_SIZE: usize = 16;
+
+pub const WIDTH: u32 = 8;
+pub const HEIGHT: u32 = 16;
+
+// One byte per row, most significant bit is the leftmost pixel
+pub fn glyph(c: char) -> &'static [u8] {
+    let index = match c as usize {
+        index if index < GLYPH_COUNT => index,
+        _                            => 0
+    };
+    &FONT[HEADER_SIZE + index * GLYPH_SIZE..HEADER_SIZE + (index + 1) * GLYPH_SIZE]
+}
@@ -0,0 +1,64 @@
+use crate::error::BootError;
+use core::cell::Cell;
+use efi::{boot::AllocateType, BootServices, MemoryType};
+
+// Memory type the region is reported with in the memory map, so the
+// kernel can tell it apart from free memory, preserve it while it's
+// needed and then reclaim it
+pub const HANDOFF_MEMORY_TYPE: u32 = 0x80000000;
+
+// Single allocation holding everything the loader passes to the kernel
+// by reference. Placed below 4GiB, as the entry trampoline and page
+// tables are accessed from 32-bit code
+pub struct Handoff {
+    base:   usize,
+    size:   usize,
+    used:   Cell<usize>,
+}
+
+impl Handoff {
+    pub fn new(bs: &BootServices, size: usize) -> Result<Self, BootError> {
+        let size = (size + 0xFFF) & !0xFFF;
+        let base = bs
+            .allocate_pages(AllocateType::MaxAddress,
+                            MemoryType::from(HANDOFF_MEMORY_TYPE),
+                            size / 0x1000,
+                            0xFFFFFFFF)
+            .map_err(BootError::MemoryAllocationError)?;
+
+        unsafe {
+            core::ptr::write_bytes(base as *mut u8, 0, size);
+        }
+
+        Ok(Handoff {
+            base,
+            size,
+            used: Cell::new(0),
+        })
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Panics if the region was sized incorrectly
+    pub fn alloc(&self, size: usize, align: usize) -> usize {
+        let start = (self.base + self.used.get() + align - 1) & !(align - 1);
+        assert!(start + size <= self.base + self.size, "Handoff region overflow");
+        self.used.set(start + size - self.base);
+        start
+    }
+
+    pub fn alloc_pages(&self, count: usize) -> usize {
+        self.alloc(count * 0x1000, 0x1000)
+    }
+
+    pub fn alloc_slice(&self, size: usize) -> &'static mut [u8] {
+        let start = self.alloc(size, 16);
+        unsafe { core::slice::from_raw_parts_mut(start as *mut u8, size) }
+    }
+}
@@ -1,16 +1,8 @@
 use crate::elf;
-use crate::error::InitrdLoadError;
+use crate::error::{BootError, InitrdLoadError};
+use crate::mem;
 use core::mem::MaybeUninit;
-use efi::{CStr16, File};
-
-fn check_placement(mmap: &efi::MemoryMap, base: usize, size: usize) -> bool {
-    for page in (base & !0xFFF..(base + size + 0xFFF) & !0xFFF).step_by(0x1000) {
-        if !mmap.is_usable_now(page) {
-            return false;
-        }
-    }
-    true
-}
+use efi::{BootServices, CStr16, File, MemoryType};
 
 fn do_load(file: &mut File, base: usize, size: usize) -> Result<(), InitrdLoadError> {
     file.read(unsafe { core::slice::from_raw_parts_mut(base as *mut u8, size) })
@@ -19,37 +11,42 @@ fn do_load(file: &mut File, base: usize, size: usize) -> Result<(), InitrdLoadEr
 }
 
 pub fn load_somewhere(
+    bs: &BootServices,
     root: &mut File,
     filename: &CStr16,
-    mmap: &efi::MemoryMap,
+    mmap: &mut efi::MemoryMap,
     obj: &elf::Object,
-) -> Result<(usize, usize), InitrdLoadError> {
+) -> Result<(usize, usize), BootError> {
     let mut statbuf: [u8; 1024] = unsafe { MaybeUninit::uninit().assume_init() };
     let mut file = root
         .open(filename, efi::proto::fp::OPEN_MODE_READ, 0)
         .map_err(InitrdLoadError::IOError)?;
     let stat = file.stat(&mut statbuf).map_err(InitrdLoadError::IOError)?;
     let size = stat.file_size as usize;
+    let memory_type = MemoryType::from(mem::INITRD_MEMORY_TYPE);
+
+    // Opening the file may have allocated memory
+    bs.get_memory_map(mmap).map_err(BootError::MemoryMapError)?;
 
     // 1. Try loading right below the kernel
     if obj.start >= size {
         let start = (obj.start - size) & !0xFFF;
+        let end = (start + size + 0xFFF) & !0xFFF;
 
-        if check_placement(mmap, start, size) {
+        if mmap.is_range_usable(start, end) {
+            mem::claim_range(bs, mmap, start, end, memory_type)?;
             println!("Loading initrd below the kernel at 0x{:016x}", start);
             do_load(&mut file, start, size)?;
             return Ok((start, size));
         }
     }
 
-    // 2. Any location above the kernel
-    for start in ((obj.end + 0x3FFF) & !0xFFF..0x100000000).step_by(0x1000) {
-        if check_placement(mmap, start, size) {
-            println!("Loading initrd at 0x{:016x}", start);
-            do_load(&mut file, start, size)?;
-            return Ok((start, size));
-        }
+    // 2. Lowest location above the kernel
+    if let Some(start) = mem::allocate_placed(bs, mmap, size, 0x1000, obj.end + 0x3000, 0x100000000, memory_type)? {
+        println!("Loading initrd at 0x{:016x}", start);
+        do_load(&mut file, start, size)?;
+        return Ok((start, size));
     }
 
-    Err(InitrdLoadError::NoSpace)
+    Err(InitrdLoadError::NoSpace.into())
 }
@@ -0,0 +1,170 @@
+use crate::{cpu, handoff::Handoff};
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+// Loader messages are recorded, one record per line, in a ring buffer the
+// kernel can replay into its own log. The buffer starts out in the loader
+// image and moves to the handoff region once that's allocated, so output
+// right up to the kernel entry is kept
+
+const LOG_DATA_SIZE: usize = 0x4000;
+// Longer lines are truncated
+const MAX_LINE: usize = 256;
+const RECORD_HEADER_SIZE: usize = size_of::<RecordHeader>();
+
+pub const SIZE: usize = size_of::<LogBuffer>() + 7;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Error   = 0,
+    Warning = 1,
+    Info    = 2,
+}
+
+// Same layout as struct yboot_log. "start" and "end" grow monotonically,
+// records live at data[offset % size] and may wrap around
+#[repr(C)]
+struct LogBuffer {
+    size:       u32,
+    tsc_per_us: u32,
+    start:      u64,
+    end:        u64,
+    data:       [u8; LOG_DATA_SIZE],
+}
+
+// struct yboot_log_record, followed by the text padded to 8 bytes
+#[repr(C)]
+struct RecordHeader {
+    timestamp:  u64,
+    length:     u16,
+    level:      u8,
+    _pad:       [u8; 5],
+}
+
+// Line being assembled, committed as a record once it's terminated
+struct Line {
+    open:       bool,
+    timestamp:  u64,
+    level:      Level,
+    length:     usize,
+    text:       [u8; MAX_LINE],
+}
+
+static mut EARLY_LOG: LogBuffer = LogBuffer {
+    size:       LOG_DATA_SIZE as u32,
+    tsc_per_us: 0,
+    start:      0,
+    end:        0,
+    data:       [0; LOG_DATA_SIZE],
+};
+
+// Null while the early buffer is in use
+static mut LOG: *mut LogBuffer = null_mut();
+
+static mut LINE: Line = Line {
+    open:       false,
+    timestamp:  0,
+    level:      Level::Info,
+    length:     0,
+    text:       [0; MAX_LINE],
+};
+
+fn buffer() -> &'static mut LogBuffer {
+    unsafe {
+        match LOG.as_mut() {
+            Some(log) => log,
+            None      => &mut EARLY_LOG
+        }
+    }
+}
+
+impl LogBuffer {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.data[(offset as usize + i) % LOG_DATA_SIZE] = *byte;
+        }
+    }
+
+    fn record_length_at(&self, offset: u64) -> usize {
+        // "length" follows the 8-byte timestamp
+        let lo = self.data[(offset as usize + 8) % LOG_DATA_SIZE] as usize;
+        let hi = self.data[(offset as usize + 9) % LOG_DATA_SIZE] as usize;
+        RECORD_HEADER_SIZE + (((hi << 8 | lo) + 7) & !7)
+    }
+
+    fn push(&mut self, line: &Line) {
+        let size = RECORD_HEADER_SIZE + ((line.length + 7) & !7);
+
+        // Drop the oldest records to make room
+        while self.end + size as u64 - self.start > LOG_DATA_SIZE as u64 {
+            self.start += self.record_length_at(self.start) as u64;
+        }
+
+        let header = RecordHeader {
+            timestamp:  line.timestamp,
+            length:     line.length as u16,
+            level:      line.level as u8,
+            _pad:       [0; 5],
+        };
+        let header = unsafe {
+            core::slice::from_raw_parts(&header as *const RecordHeader as *const u8, RECORD_HEADER_SIZE)
+        };
+
+        self.write_at(self.end, header);
+        self.write_at(self.end + RECORD_HEADER_SIZE as u64, &line.text[..line.length]);
+        self.write_at(self.end + (RECORD_HEADER_SIZE + line.length) as u64,
+                      &[0; 7][..size - RECORD_HEADER_SIZE - line.length]);
+        self.end += size as u64;
+    }
+}
+
+pub fn write(level: Level, s: &str) {
+    let line = unsafe { &mut LINE };
+
+    for byte in s.bytes() {
+        if !line.open {
+            line.open = true;
+            line.timestamp = cpu::rdtsc();
+            line.level = level;
+            line.length = 0;
+        }
+
+        match byte {
+            b'\r' => {}
+            b'\n' => {
+                buffer().push(line);
+                line.open = false;
+            }
+            _ => {
+                if line.length < MAX_LINE {
+                    line.text[line.length] = byte;
+                    line.length += 1;
+                }
+            }
+        }
+    }
+}
+
+// Moves the log into the handoff region, which must have SIZE bytes left.
+// Returns the new address
+pub fn relocate(handoff: &Handoff) -> usize {
+    let addr = handoff.alloc(size_of::<LogBuffer>(), 8);
+    let log = addr as *mut LogBuffer;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(buffer() as *const LogBuffer, log, 1);
+        (*log).tsc_per_us = cpu::tsc_per_us() as u32;
+        LOG = log;
+    }
+    addr
+}
+
+pub struct Writer(pub Level);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(self.0, s);
+        Ok(())
+    }
+}
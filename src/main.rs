@@ -1,4 +1,4 @@
-#![feature(asm, const_fn, llvm_asm)]
+#![feature(asm, const_fn, global_asm, llvm_asm)]
 #![no_main]
 #![no_std]
 
@@ -12,36 +12,152 @@ use core::convert::TryInto;
 use efi::{
     image_handle, system_table, CStr16, ConfigurationTableEntry, ImageHandle, Status, SystemTable,
 };
-use yboot2_proto::{LoadProtocol, MemoryMapInfo, ProtoV1};
+use yboot2_proto::{video::PixelFormat, AllocRequest, LoadProtocol, MemoryMapInfo, ProtoV1};
 
 #[macro_use]
 mod println;
+mod backtrace;
+mod config;
+mod console;
+mod cpu;
+mod editor;
 mod elf;
+mod entry;
 mod error;
+mod font;
+mod handoff;
 mod initrd;
+mod log;
 mod mem;
+mod menu;
+mod serial;
+mod smp;
+mod splash;
 mod video;
 
 use error::BootError;
 
-fn set_efi_mmap<T: LoadProtocol>(data: &mut T, mmap: &efi::MemoryMap) -> Result<(), BootError> {
-    match data.set_mmap(&MemoryMapInfo {
+// Extra descriptors to reserve space for in the final memory map, as the
+// map grows with the loader's own allocations
+const MMAP_SLACK: usize = 32;
+
+fn set_efi_mmap<T: LoadProtocol>(data: &mut T, mmap: &efi::MemoryMap) {
+    data.set_mmap(&MemoryMapInfo {
         address: mmap.storage_ref.as_ptr() as u64,
         entsize: mmap.descriptor_size.try_into().unwrap(),
         size: mmap.size.try_into().unwrap(),
-    }) {
-        Err(_) => Err(BootError::MemoryMapError(efi::Status::Err)),
-        Ok(()) => Ok(()),
+    });
+}
+
+// Size, alignment and upper limit of an allocation request, None if it
+// can't be satisfied
+fn request_placement(req: &AllocRequest) -> Option<(usize, usize, usize)> {
+    let align = core::cmp::max(req.align as usize, 0x1000);
+    let max_addr = match req.max_address as usize {
+        0    => usize::MAX,
+        addr => addr
+    };
+
+    if !align.is_power_of_two() {
+        return None;
+    }
+    Some(((req.size as usize + 0xFFF) & !0xFFF, align, max_addr))
+}
+
+// Satisfies the kernel's allocation requests, placing them like the
+// initrd. Requests which don't fit are left with base = 0 for
+// place_requests_after_exit()
+fn allocate_requests<T: LoadProtocol>(
+    bs: &efi::BootServices,
+    mmap: &mut efi::MemoryMap,
+    data: &mut T,
+) -> Result<(), BootError> {
+    let memory_type = efi::MemoryType::from(mem::ALLOC_MEMORY_TYPE);
+
+    for req in data.get_alloc_requests().iter_mut().filter(|req| req.size != 0) {
+        req.base = match request_placement(req) {
+            Some((size, align, max_addr)) => {
+                mem::allocate_placed(bs, mmap, size, align, 0x100000, max_addr, memory_type)?.unwrap_or(0) as u64
+            }
+            None => 0
+        };
     }
+
+    Ok(())
+}
+
+// Places the requests allocate_requests() couldn't satisfy in the final
+// memory map. The loader doesn't write to them, so boot services memory,
+// which the kernel would reclaim anyway, can be used along with free
+// memory. Requests which still don't fit are reported with base = 0
+fn place_requests_after_exit<T: LoadProtocol>(mmap: &mut efi::MemoryMap, data: &mut T) -> Result<(), BootError> {
+    let memory_type = efi::MemoryType::from(mem::ALLOC_MEMORY_TYPE);
+    let usable = efi::MemoryDescriptor::is_usable_after_exit;
+
+    for req in data.get_alloc_requests().iter_mut().filter(|req| req.size != 0 && req.base == 0) {
+        let placement = request_placement(req).and_then(|(size, align, max_addr)| {
+            let base = mmap.find_free_with(size, align, 0x100000, max_addr, usable)?;
+            Some((base, size))
+        });
+
+        match placement {
+            Some((base, size)) => {
+                mmap.set_range_type(base, base + size, memory_type, usable)
+                    .map_err(BootError::MemoryMapError)?;
+                req.base = base as u64;
+            }
+            None => warnln!("Failed to allocate 0x{:x} bytes for the kernel", req.size),
+        }
+    }
+
+    Ok(())
+}
+
+// Converts a path from the config to UTF-16 in `buf`
+fn file_path<'a>(path: &str, buf: &'a mut [u16]) -> Result<&'a CStr16, BootError> {
+    CStr16::from_str_in(path, buf).ok_or(BootError::FileError(Status::InvalidParameter))
 }
 
 fn main() -> Result<(), BootError> {
     let mut desc_array = [0u8; 16384];
     let mut mmap = efi::MemoryMap::new(&mut desc_array);
     let bs = &system_table().boot_services;
+    // Timestamps of the log and AP startup delays are based on the TSC
+    cpu::calibrate_tsc(bs);
+    let hotkeys = menu::Hotkeys::register();
 
-    bs.get_memory_map(&mut mmap)
-        .map_err(BootError::MemoryMapError)?;
+    let mut root = image_handle()
+        .get_boot_path()
+        .map_err(BootError::FileError)?
+        .open_partition()
+        .map_err(BootError::FileError)?;
+
+    let config = config::Config::load(&mut root, CStr16::from_literal(cstr16!(r"\yboot.cfg")))?;
+    if let Some(port) = config.serial {
+        if !serial::init(port, config.serial_baud) {
+            warnln!("No UART found at port 0x{:x}", port);
+        }
+    }
+    let mut output = video::select_output(bs, &config);
+    let boot_entry = menu::select(&config, hotkeys);
+
+    let splash = config.splash.and_then(|path| {
+        let mut buf = [0u16; 256];
+        let res = file_path(path, &mut buf).and_then(|path| splash::Splash::load(bs, &mut root, path));
+        match res {
+            Ok(splash) => Some(splash),
+            Err(err) => {
+                warnln!("Failed to load splash image: {}", err);
+                None
+            }
+        }
+    });
+    if let (Some(splash), Some(output)) = (&splash, &mut output) {
+        splash.draw(output.gop);
+    }
+
+    // Keep excluded ranges out of any placement decisions
+    mem::claim_exclusions(bs, &mut mmap, &config)?;
 
     let rsdp = system_table()
         .config_iter()
@@ -51,23 +167,36 @@ fn main() -> Result<(), BootError> {
             _ => panic!(),
         });
 
-    let mut root = image_handle()
-        .get_boot_path()
-        .map_err(BootError::FileError)?
-        .open_partition()
-        .map_err(BootError::FileError)?;
-
     // Load kernel
-    let mut obj = elf::Object::open(&mut root, CStr16::from_literal(cstr16!(r"\kernel.elf")))?;
-    let entry = obj.load(&mmap)?;
+    let mut path_buf = [0u16; 256];
+    let mut obj = elf::Object::open(&mut root, file_path(boot_entry.entry.kernel, &mut path_buf)?)?;
+    // Opening files and the splash may have allocated memory. The range is
+    // claimed before anything is written to it
+    bs.get_memory_map(&mut mmap).map_err(BootError::MemoryMapError)?;
+    obj.check_segments(&mmap)?;
+    mem::claim_range(bs, &mmap, obj.start, obj.end, efi::MemoryType::from(mem::KERNEL_MEMORY_TYPE))?;
+    let entry = obj.load()?;
     let data = obj.locate_protocol_data::<ProtoV1>()?;
 
+    let la57 = (data.get_flags() & yboot2_proto::FLAG_LA57) != 0;
+    if la57 && !cpu::has_la57() {
+        return Err(BootError::La57Unsupported);
+    }
+
+    // Runtime services are only usable from the upper mirror
+    let upper = (data.get_flags() & yboot2_proto::FLAG_UPPER) != 0;
+    let runtime = (data.get_flags() & yboot2_proto::FLAG_EFI_RUNTIME) != 0;
+    if runtime && !upper {
+        return Err(BootError::RuntimeWithoutUpper);
+    }
+
     if (data.get_flags() & yboot2_proto::FLAG_INITRD) != 0 {
         // Load initrd
         let (initrd_base, initrd_size) = initrd::load_somewhere(
+            bs,
             &mut root,
-            CStr16::from_literal(cstr16!(r"\initrd.img")),
-            &mmap,
+            file_path(boot_entry.entry.initrd, &mut path_buf)?,
+            &mut mmap,
             &obj,
         )?;
 
@@ -77,35 +206,147 @@ fn main() -> Result<(), BootError> {
         data.set_initrd(0, 0);
     }
 
+    allocate_requests(bs, &mut mmap, data)?;
+
     data.set_acpi_rsdp(rsdp.unwrap_or(core::ptr::null_mut()) as usize);
     data.set_loader_magic();
+    if data.set_cmdline(boot_entry.cmdline()).is_err() {
+        warnln!("Kernel command line is too long, ignored");
+    }
 
+    let edid = output.as_ref().and_then(|output| output.edid);
+    let mut framebuffer = None;
     if (data.get_flags() & yboot2_proto::FLAG_VIDEO) != 0 {
-        video::set_mode(bs, data)?;
+        let request = data.get_video_info().map_err(|_| BootError::VideoModeUnsupported)?;
+        let text = request.format == PixelFormat::Text;
+        let console = if text {
+            video::set_text(output.as_mut(), data)?
+        } else {
+            video::set_mode(output.as_mut(), data)?
+        };
+
+        // The mode switch clears the screen. The splash is redrawn before
+        // the console attaches, as it would wipe the console's output
+        let splash_drawn = match (&splash, &mut output) {
+            (Some(splash), Some(output)) => {
+                splash.draw(output.gop);
+                true
+            }
+            _ => false
+        };
+        // Without a mode switch the firmware's output is still there
+        if let Some((video, mask)) = console {
+            console::attach(&video, &mask, text && !splash_drawn);
+
+            let start = video.framebuffer as usize;
+            if start != 0 {
+                framebuffer = Some((start, start + video.pitch as usize * video.height as usize));
+            }
+        }
+    }
+
+    let output_path = output.as_ref().and_then(|output| output.path.as_ref()).map(video::PathText::as_str);
+
+    // Place everything passed to the kernel in the handoff region
+    // while it's still possible to allocate memory
+    let mut tables = mem::PageTables::new(&mmap, framebuffer, la57);
+    let symbols = obj.locate_symbols()?;
+    // Each allocation request placed after ExitBootServices() may split
+    // descriptors at both ends
+    let mmap_slack = MMAP_SLACK + config.mmap_slack() + yboot2_proto::MAX_ALLOCS * 2;
+    let mmap_size = mmap.size + mmap_slack * mmap.descriptor_size;
+
+    let park = (data.get_flags() & yboot2_proto::FLAG_SMP_PARK) != 0;
+    let mut cpus = smp::Processors::query(bs, park)?;
+
+    let handoff = handoff::Handoff::new(
+        bs,
+        tables.size()
+            + entry::Trampoline::SIZE
+            + cpus.size()
+            + mmap_size
+            + symbols.as_ref().map_or(0, elf::Symbols::size)
+            + edid.map_or(0, |edid| edid.len() + 15)
+            + output_path.map_or(0, |path| path.len() + 1 + 15)
+            + log::SIZE,
+    )?;
+    data.set_handoff(handoff.base(), handoff.size());
+    data.set_log(log::relocate(&handoff));
+
+    tables.place(&handoff);
+    let trampoline = entry::Trampoline::new(&handoff);
+    cpus.place(&handoff)?;
+    let (cpu_table, cpu_count) = cpus.table();
+    data.set_cpus(cpu_table, cpu_count);
+    let mmap_buffer = handoff.alloc_slice(mmap_size);
+
+    if let Some(edid) = edid {
+        let copy = handoff.alloc_slice(edid.len());
+        copy.copy_from_slice(edid);
+        data.set_edid(copy.as_ptr() as usize, copy.len());
+    }
+
+    let output_number = output.as_ref().and_then(|output| output.number);
+    match (output_number, output_path) {
+        (Some(number), Some(path)) => {
+            let copy = handoff.alloc_slice(path.len() + 1);
+            copy[..path.len()].copy_from_slice(path.as_bytes());
+            copy[path.len()] = 0;
+            data.set_video_output(number as u64, copy.as_ptr() as usize);
+        }
+        _ => data.set_video_output(yboot2_proto::VIDEO_OUTPUT_DEFAULT, 0),
     }
 
-    // Get the new memory map and terminate boot services
+    if let Some(symbols) = symbols {
+        let loaded = obj.load_symbols(&symbols, &handoff)?;
+        data.set_elf_symbols(
+            loaded.symtab_hdr,
+            loaded.symtab_data,
+            loaded.strtab_hdr,
+            loaded.strtab_data,
+        );
+    }
+
+    let stack_size = match data.get_stack_size() as usize {
+        0    => entry::DEFAULT_STACK_SIZE,
+        size => (size + 0xFFF) & !0xFFF
+    };
+    let stack_base = entry::allocate_stack(bs, stack_size)?;
+    data.set_stack(stack_base, stack_size);
+
+    // Get the final memory map and terminate boot services
+    let mut mmap = efi::MemoryMap::new(mmap_buffer);
     bs.get_memory_map(&mut mmap).map_err(BootError::MemoryMapError)?;
     bs.exit_boot_services(mmap.key).map_err(BootError::TerminateServicesError)?;
-    set_efi_mmap(data, &mmap)?;
 
-    // Setup upper virtual mapping if requested
-    let real_entry: usize;
-    if (data.get_flags() & yboot2_proto::FLAG_UPPER) != 0 {
-        mem::setup_upper();
+    if runtime {
+        data.set_efi_runtime(mem::virtualize_runtime(&mut mmap)?);
+    }
+    mem::apply_exclusions(&mut mmap, &config)?;
+    place_requests_after_exit(&mut mmap, data)?;
+    set_efi_mmap(data, &mmap);
 
+    // Identity-map physical memory, setup upper virtual mapping if requested.
+    // The tables rely on the loader's PAT layout for write-combining
+    let pat = mem::setup_pat();
+    data.set_pat(pat);
+    tables.setup(upper);
+
+    // APs run on the same tables, so they're only started now
+    cpus.park(&tables, &trampoline, pat);
+
+    let real_entry: usize;
+    if upper {
         real_entry = entry;
     } else {
-        real_entry = if entry >= 0xFFFFFF0000000000 {
-            entry - 0xFFFFFF0000000000
+        real_entry = if entry >= mem::UPPER_BASE {
+            entry - mem::UPPER_BASE
         } else {
             entry
         };
     }
-    unsafe {
-        llvm_asm!("xor %rbp, %rbp; jmp *$0"::"{di}"(real_entry));
-    }
-    loop {}
+    let data_addr = data as *mut ProtoV1 as usize;
+    trampoline.enter_kernel(&tables, real_entry, data_addr, stack_base + stack_size);
 }
 
 #[no_mangle]
@@ -114,19 +355,38 @@ extern "C" fn efi_main(ih: *mut ImageHandle, st: *mut SystemTable) -> u64 {
     let res = &main();
     // Don't return immediately on failure
     if let Err(err) = res {
-        let bs = &system_table().boot_services;
-        println!("yboot2 error: {}", err);
+        errorln!("yboot2 error: {}", err);
+        // Failures after ExitBootServices() have nothing to return to, the
+        // message still reaches the serial port and the framebuffer console
+        if !efi::boot_services_active() {
+            cpu::halt();
+        }
         // Delay for 5s so error message can be read
-        bs.stall(5000000);
+        system_table().boot_services.stall(5000000);
     }
 
     efi::Termination::to_efi(&res.as_ref().map_err(efi::Status::from))
 }
 
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 #[panic_handler]
 fn panic(panic: &PanicInfo<'_>) -> ! {
-    // TODO: check if BS are available
-    println!("Panic: {}!", panic);
-    system_table().boot_services.exit(Status::Err);
+    // A panic while reporting one would only recurse
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        errorln!("Panic: {}!", panic);
+        backtrace::print();
+    }
+
+    // Output still reaches the serial port and the framebuffer console
+    // after ExitBootServices(), but there's nothing to return to
+    if efi::boot_services_active() {
+        let bs = &system_table().boot_services;
+        bs.stall(5000000);
+        bs.exit(Status::Aborted);
+    }
+    cpu::halt();
 }
@@ -1,36 +1,377 @@
-unsafe fn load_cr3(value: usize) {
-    llvm_asm!("mov $0, %cr3"::"r"(value):"memory");
-}
+use crate::config::Config;
+use crate::cpu;
+use crate::error::BootError;
+use crate::handoff::Handoff;
+use core::cell::Cell;
+use efi::{
+    boot::AllocateType, system_table, BootServices, MemoryAttribute, MemoryDescriptor, MemoryMap,
+    MemoryType,
+};
 
+const PAGE_HUGE_PAT: u64 = 1 << 12;
 const PAGE_HUGE: u64 = 1 << 7;
+const PAGE_PAT: u64 = 1 << 7;
 const PAGE_USER: u64 = 1 << 2;
 const PAGE_WRITE: u64 = 1 << 1;
 const PAGE_PRESENT: u64 = 1 << 0;
 
-// 1 PML4, 1 PDPT, 4 PD
-// TODO: maybe setup this in compile-time?
-static mut TABLES: [u64; 512 * 6] = [0; 512 * 6];
+const MIB2: usize = 1 << 21;
+const GIB: usize = 1 << 30;
+const PDPT_SPAN: usize = 512 * GIB;
+
+// The upper mirror at 0xFFFFFF0000000000 starts at PML4 entry 510 and
+// covers what's left of the address space. With 5-level paging the same
+// PML4 is also referenced from the last PML5 entry
+const UPPER_PML4_INDEX: usize = 510;
+const UPPER_PDPT_COUNT: usize = 2;
+const UPPER_PML5_INDEX: usize = 511;
+
+pub const UPPER_BASE: usize = 0xFFFFFF0000000000;
+const UPPER_SIZE: usize = UPPER_PDPT_COUNT * PDPT_SPAN;
+
+const MSR_IA32_PAT: u32 = 0x277;
+
+// PA0..PA3 keep their power-on values, so PAT/PCD/PWT = 0 is still WB:
+//  PA0 = WB, PA1 = WT, PA2 = UC-, PA3 = UC,
+//  PA4 = WC, PA5 = WP, PA6 = UC-, PA7 = UC
+pub const PAT_LAYOUT: u64 = 0x0007050100070406;
+// PA4: PAT = 1, PCD = 0, PWT = 0
+const PAT_WC_HUGE: u64 = PAGE_HUGE_PAT;
+const PAT_WC: u64 = PAGE_PAT;
 
-unsafe fn setup_tables() {
-    for i in 0 .. 512 * 4 {
-        // pd[i] = 2MiB block
-        TABLES[i + 512 * 2] = (i << 21) as u64 | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+// Programs IA32_PAT, returning the layout set or 0 if PAT isn't supported.
+// Should only be called after boot services are terminated. Follows the
+// SDM sequence for changing memory types: interrupts off, caches in no-fill
+// mode (CR0.CD = 1, NW = 0) and flushed along with the TLBs (including
+// global pages, by clearing CR4.PGE) before and after the write. Parked
+// APs get the same layout from their startup code
+pub fn setup_pat() -> u64 {
+    if !cpu::has_pat() {
+        return 0;
     }
 
-    for i in 0 .. 4 {
-        // pdpt[i] = pd_i
-        TABLES[512 + i] = (&TABLES[i * 512 + 512 * 2]) as *const _ as u64 | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+    unsafe {
+        llvm_asm!(r#"
+            cli
+            mov %cr0, %r8
+            mov %r8, %r10
+            and $$~0x20000000, %r10
+            or $$0x40000000, %r10
+            mov %r10, %cr0
+            wbinvd
+
+            mov %cr4, %r9
+            mov %r9, %r10
+            and $$~0x80, %r10
+            mov %r10, %cr4
+            mov %cr3, %r10
+            mov %r10, %cr3
+
+            wrmsr
+
+            wbinvd
+            mov %cr3, %r10
+            mov %r10, %cr3
+            mov %r8, %cr0
+            mov %r9, %cr4
+        "#::"{ecx}"(MSR_IA32_PAT), "{eax}"(PAT_LAYOUT as u32), "{edx}"((PAT_LAYOUT >> 32) as u32)
+           :"r8", "r9", "r10", "memory":"volatile");
     }
 
-    // pml4[0] = PRESENT | pdpt
-    TABLES[0] = (&TABLES[512]) as *const _ as u64 | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
-    // pml4[510] = PRESENT | pdpt
-    TABLES[510] = (&TABLES[512]) as *const _ as u64 | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+    PAT_LAYOUT
 }
 
-pub fn setup_upper() {
-    unsafe {
-        setup_tables();
-        load_cr3(TABLES.as_ptr() as usize);
+// Moves the firmware's runtime regions into the upper mirror and
+// returns the new address of the runtime services table. Must be called
+// after ExitBootServices() with the map used to terminate them
+pub fn virtualize_runtime(mmap: &mut MemoryMap) -> Result<usize, BootError> {
+    for item in mmap.iter_mut().into_iter().flatten() {
+        if !item.attribute.contains(MemoryAttribute::RUNTIME) {
+            continue;
+        }
+        if item.end() > UPPER_SIZE {
+            return Err(BootError::RuntimeMappingError(efi::Status::InvalidParameter));
+        }
+        item.virtual_start = item.physical_start + UPPER_BASE;
+    }
+
+    let rt = &system_table().runtime_services;
+    let rt_addr = &**rt as *const _ as usize;
+    rt.set_virtual_address_map(mmap).map_err(BootError::RuntimeMappingError)?;
+
+    Ok(rt_addr + UPPER_BASE)
+}
+
+// Memory types of the kernel image, initrd and kernel-requested
+// allocations, see HANDOFF_MEMORY_TYPE
+pub const KERNEL_MEMORY_TYPE: u32 = 0x80000001;
+pub const INITRD_MEMORY_TYPE: u32 = 0x80000002;
+pub const ALLOC_MEMORY_TYPE: u32 = 0x80000003;
+
+// Allocates the free pages within [start, end) according to `mmap`,
+// which must be up to date
+pub fn claim_range(bs: &BootServices,
+                   mmap: &MemoryMap,
+                   start: usize,
+                   end: usize,
+                   memory_type: MemoryType) -> Result<(), BootError> {
+    let start = start & !0xFFF;
+    let end = end.saturating_add(0xFFF) & !0xFFF;
+
+    for region in mmap.free_regions() {
+        let a = core::cmp::max(region.start, start);
+        let b = core::cmp::min(region.end, end);
+        if a >= b {
+            continue;
+        }
+
+        bs.allocate_pages(AllocateType::Address, memory_type, (b - a) / 0x1000, a)
+            .map_err(BootError::MemoryAllocationError)?;
+    }
+    Ok(())
+}
+
+// Finds the lowest `size` bytes of free memory within [min_addr, max_addr)
+// and allocates them. `mmap` is refreshed first, so earlier allocations
+// are taken into account
+pub fn allocate_placed(bs: &BootServices,
+                       mmap: &mut MemoryMap,
+                       size: usize,
+                       align: usize,
+                       min_addr: usize,
+                       max_addr: usize,
+                       memory_type: MemoryType) -> Result<Option<usize>, BootError> {
+    bs.get_memory_map(mmap).map_err(BootError::MemoryMapError)?;
+
+    let align = core::cmp::max(align, 0x1000);
+    let size = (size + 0xFFF) & !0xFFF;
+    match mmap.find_free_above(size, align, min_addr, max_addr) {
+        Some(addr) => {
+            bs.allocate_pages(AllocateType::Address, memory_type, size / 0x1000, addr)
+                .map_err(BootError::MemoryAllocationError)?;
+            Ok(Some(addr))
+        }
+        None => Ok(None)
+    }
+}
+
+// Allocates the free pages in ranges excluded by the config, so neither
+// the loader nor the firmware places anything there. The pages end up in
+// the final memory map with the type the kernel should see. `mmap` is
+// refreshed along the way and is up to date on return
+pub fn claim_exclusions(bs: &BootServices, mmap: &mut MemoryMap, config: &Config) -> Result<(), BootError> {
+    for range in config.exclusions() {
+        bs.get_memory_map(mmap).map_err(BootError::MemoryMapError)?;
+        claim_range(bs, mmap, range.start, range.end, range.memory_type())?;
+    }
+
+    bs.get_memory_map(mmap).map_err(BootError::MemoryMapError)?;
+    Ok(())
+}
+
+// Applies the exclusions to the final memory map. Boot services memory
+// in the ranges is retyped as well, as the kernel would reclaim it otherwise
+pub fn apply_exclusions(mmap: &mut MemoryMap, config: &Config) -> Result<(), BootError> {
+    for range in config.exclusions() {
+        mmap.set_range_type(range.start,
+                            range.end,
+                            range.memory_type(),
+                            MemoryDescriptor::is_usable_after_exit)
+            .map_err(BootError::MemoryMapError)?;
+    }
+    Ok(())
+}
+
+// Identity-mapping page tables, placed in the handoff region while boot
+// services are still available and filled in right before the kernel is
+// entered.
+// Table 0 is the root (PML5 or PML4), followed by the PML4 (if 5-level)
+// and PDPTs. PDs and PTs are handed out from the rest of the pool as needed
+pub struct PageTables {
+    base:       usize,
+    count:      usize,
+    next:       Cell<usize>,
+    limit:      usize,
+    huge_1g:    bool,
+    la57:       bool,
+    // Write-combining range, e.g. the framebuffer
+    wc:         Option<(usize, usize)>,
+}
+
+fn intersects(range: Option<(usize, usize)>, start: usize, end: usize) -> bool {
+    range.map_or(false, |(a, b)| a < end && b > start)
+}
+
+fn covers(range: Option<(usize, usize)>, start: usize, end: usize) -> bool {
+    range.map_or(false, |(a, b)| a <= start && b >= end)
+}
+
+impl PageTables {
+    // Everything below 4GiB is always mapped, as the APIC, IOAPIC and PCI
+    // windows are usually not described by the memory map. The mapping is
+    // extended to cover the write-combining range (framebuffer) too
+    pub fn new(mmap: &MemoryMap, wc: Option<(usize, usize)>, la57: bool) -> Self {
+        let mut end = core::cmp::max(0x100000000, wc.map_or(0, |(_, b)| b));
+        for item in mmap.iter().into_iter().flatten() {
+            if item.end() > end {
+                end = item.end();
+            }
+        }
+
+        let mut tables = PageTables {
+            base:       0,
+            count:      0,
+            next:       Cell::new(0),
+            limit:      (end + GIB - 1) & !(GIB - 1),
+            huge_1g:    cpu::has_1gib_pages(),
+            la57,
+            wc:         if cpu::has_pat() {
+                wc.map(|(a, b)| (a & !0xFFF, (b + 0xFFF) & !0xFFF))
+            } else {
+                None
+            },
+        };
+        tables.count = tables.fixed_count() + tables.pd_count() + tables.pt_count();
+        tables
+    }
+
+    pub fn size(&self) -> usize {
+        self.count * 0x1000
+    }
+
+    // CR3 is loaded from 32-bit code by the entry trampoline, so the
+    // handoff region being below 4GiB is relied on here
+    pub fn place(&mut self, handoff: &Handoff) {
+        self.base = handoff.alloc_pages(self.count);
+    }
+
+    fn pdpt_count(&self) -> usize {
+        (self.limit + PDPT_SPAN - 1) / PDPT_SPAN
+    }
+
+    fn pd_count(&self) -> usize {
+        if self.huge_1g {
+            // Only the gigabytes with WC memory are split
+            (0..self.limit / GIB)
+                .filter(|&gib| intersects(self.wc, gib * GIB, (gib + 1) * GIB))
+                .count()
+        } else {
+            self.limit / GIB
+        }
+    }
+
+    fn pt_count(&self) -> usize {
+        // Only the 2MiB blocks at the edges of WC range are split
+        match self.wc {
+            Some((a, b)) => (a % MIB2 != 0) as usize + (b % MIB2 != 0) as usize,
+            None         => 0
+        }
+    }
+
+    fn pml4_index(&self) -> usize {
+        self.la57 as usize
+    }
+
+    fn fixed_count(&self) -> usize {
+        self.pml4_index() + 1 + self.pdpt_count()
+    }
+
+    fn table_addr(&self, index: usize) -> u64 {
+        (self.base + index * 0x1000) as u64
+    }
+
+    fn table(&self, index: usize) -> &'static mut [u64; 512] {
+        unsafe { &mut *(self.table_addr(index) as *mut _) }
+    }
+
+    fn alloc_table(&self) -> usize {
+        let index = self.next.get();
+        assert!(index < self.count);
+        self.next.set(index + 1);
+        index
+    }
+
+    fn map_4k(&self, start: usize) -> u64 {
+        let index = self.alloc_table();
+        let pt = self.table(index);
+        for i in 0..512 {
+            let phys = start + i * 0x1000;
+            let cache = if covers(self.wc, phys, phys + 0x1000) {
+                PAT_WC
+            } else {
+                0
+            };
+            // pt[i] = 4KiB page
+            pt[i] = phys as u64 | cache | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+        }
+        self.table_addr(index) | PAGE_WRITE | PAGE_PRESENT | PAGE_USER
+    }
+
+    fn map_2m(&self, start: usize) -> u64 {
+        let index = self.alloc_table();
+        let pd = self.table(index);
+        for i in 0..512 {
+            let phys = start + i * MIB2;
+            pd[i] = if covers(self.wc, phys, phys + MIB2) {
+                phys as u64 | PAT_WC_HUGE | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT | PAGE_USER
+            } else if intersects(self.wc, phys, phys + MIB2) {
+                self.map_4k(phys)
+            } else {
+                // pd[i] = 2MiB block
+                phys as u64 | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT | PAGE_USER
+            };
+        }
+        self.table_addr(index) | PAGE_WRITE | PAGE_PRESENT | PAGE_USER
+    }
+
+    // Should only be called after boot services are terminated, as
+    // the firmware expects its own mappings to stay in place
+    pub fn setup(&self, upper: bool) {
+        let pdpt_base = self.pml4_index() + 1;
+
+        for i in 0..self.count {
+            self.table(i).iter_mut().for_each(|e| *e = 0);
+        }
+        self.next.set(self.fixed_count());
+
+        for gib in 0..self.limit / GIB {
+            let pdpt = self.table(pdpt_base + gib / 512);
+            let phys = gib * GIB;
+
+            pdpt[gib % 512] = if self.huge_1g && !intersects(self.wc, phys, phys + GIB) {
+                // pdpt[i] = 1GiB block
+                phys as u64 | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT | PAGE_USER
+            } else {
+                self.map_2m(phys)
+            };
+        }
+
+        let pml4 = self.table(self.pml4_index());
+        for i in 0..self.pdpt_count() {
+            pml4[i] = self.table_addr(pdpt_base + i) | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+        }
+
+        if upper {
+            for i in 0..core::cmp::min(self.pdpt_count(), UPPER_PDPT_COUNT) {
+                pml4[UPPER_PML4_INDEX + i] = pml4[i];
+            }
+        }
+
+        if self.la57 {
+            let pml5 = self.table(0);
+            pml5[0] = self.table_addr(self.pml4_index()) | PAGE_WRITE | PAGE_PRESENT | PAGE_USER;
+            if upper {
+                pml5[UPPER_PML5_INDEX] = pml5[0];
+            }
+        }
+    }
+
+    pub fn is_5level(&self) -> bool {
+        self.la57
+    }
+
+    // Tables are activated by the entry trampoline
+    pub fn root(&self) -> usize {
+        self.base
     }
 }
@@ -0,0 +1,273 @@
+use crate::config::{Config, Entry};
+use crate::editor::{Line, LineEditor};
+use core::ffi::c_void;
+use core::fmt::Write;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use efi::{
+    stip::{InputKey, SCAN_DOWN, SCAN_NULL, SCAN_UP},
+    stipex::{KeyData, KEY_STATE_EXPOSED, LEFT_CONTROL_PRESSED, RIGHT_CONTROL_PRESSED},
+    stop::Color,
+    system_table, SimpleTextInputExProtocol, Status,
+};
+
+// Boot menu drawn on the firmware console. It's shown with a countdown if
+// the config sets a timeout. Otherwise the default entry boots right away,
+// unless Shift is held while the loader starts. Ctrl+E pressed at that
+// point goes straight to editing the default entry's command line
+
+// Polling interval of the countdown, in microseconds
+const TICK: u64 = 10000;
+const TICKS_PER_SECOND: u32 = 100;
+
+const FIRST_ROW: usize = 3;
+
+static CTRL_E_PRESSED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_ctrl_e(_: *const KeyData) -> u64 {
+    CTRL_E_PRESSED.store(true, Ordering::Relaxed);
+    0
+}
+
+
+// Watches for the menu hotkeys from the start of the loader until the
+// menu decision is made
+pub struct Hotkeys {
+    input:      Option<&'static mut SimpleTextInputExProtocol>,
+    handles:    [*mut c_void; 2],
+}
+
+impl Hotkeys {
+    pub fn register() -> Self {
+        let mut handles = [null_mut(); 2];
+        let mut input = system_table().con_in_ex().ok();
+
+        if let Some(input) = input.as_mut() {
+            // Have the firmware report Shift being held on its own
+            input.set_state(KEY_STATE_EXPOSED).ok();
+
+            let key = InputKey { scan_code: SCAN_NULL, unicode_char: 'e' as u16 };
+            for (handle, control) in handles.iter_mut().zip(&[LEFT_CONTROL_PRESSED, RIGHT_CONTROL_PRESSED]) {
+                *handle = input.register_key_notify(key, *control, on_ctrl_e).unwrap_or(null_mut());
+            }
+        }
+
+        Hotkeys { input, handles }
+    }
+
+    // Stops watching, returns true if Shift is held. Keystrokes queued up
+    // so far are consumed
+    fn finish(self) -> bool {
+        let mut shift = false;
+
+        if let Some(input) = self.input {
+            while let Ok((key, state)) = input.read_key_or_state() {
+                shift |= state.shift();
+                match key {
+                    Some(key) => {
+                        // In case key notifications aren't supported
+                        if state.control() && is_edit_key(key.unicode_char) {
+                            CTRL_E_PRESSED.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    None => break
+                }
+            }
+
+            for handle in self.handles.iter().filter(|handle| !handle.is_null()) {
+                input.unregister_key_notify(*handle).ok();
+            }
+            input.set_state(0).ok();
+        }
+
+        shift
+    }
+}
+
+// Entry picked to boot, with the command line if it was edited
+pub struct Selection {
+    pub entry:  Entry,
+    edited:     Option<Line>,
+}
+
+impl Selection {
+    fn new(entry: Entry) -> Self {
+        Selection {
+            entry,
+            edited: None,
+        }
+    }
+
+    pub fn cmdline(&self) -> &str {
+        self.edited.as_ref().map_or(self.entry.cmdline, Line::as_str)
+    }
+}
+
+struct Menu<'a> {
+    entries:    &'a [Entry],
+    selected:   usize,
+}
+
+impl Menu<'_> {
+    fn draw(&self) {
+        let out = &mut system_table().con_out;
+        out.set_color(Color::LightGray, Color::Black).ok();
+        out.clear_screen().ok();
+        out.enable_cursor(false).ok();
+
+        out.set_cursor_position(2, 1).ok();
+        out.set_color(Color::White, Color::Black).ok();
+        write!(out, "yboot2").ok();
+
+        for num in 0..self.entries.len() {
+            self.draw_entry(num);
+        }
+
+        out.set_color(Color::DarkGray, Color::Black).ok();
+        out.set_cursor_position(2, FIRST_ROW + self.entries.len() + 1).ok();
+        write!(out, "Up/Down to select, Enter to boot, E to edit the command line").ok();
+    }
+
+    fn draw_entry(&self, num: usize) {
+        let out = &mut system_table().con_out;
+        let columns = screen_columns();
+
+        if num == self.selected {
+            out.set_color(Color::Black, Color::LightGray).ok();
+        } else {
+            out.set_color(Color::LightGray, Color::Black).ok();
+        }
+        out.set_cursor_position(2, FIRST_ROW + num).ok();
+        // Pad the highlight to the screen width
+        write!(out, " {:width$}", self.entries[num].title, width = columns.saturating_sub(6)).ok();
+    }
+
+    fn select(&mut self, num: usize) {
+        let previous = self.selected;
+        self.selected = num;
+        self.draw_entry(previous);
+        self.draw_entry(num);
+    }
+
+    fn draw_countdown(&self, seconds: Option<u32>) {
+        let out = &mut system_table().con_out;
+        out.set_color(Color::LightGray, Color::Black).ok();
+        out.set_cursor_position(2, FIRST_ROW + self.entries.len() + 2).ok();
+        match seconds {
+            Some(seconds) => write!(out, "Booting in {} s ", seconds),
+            None          => write!(out, "{:16}", ""),
+        }.ok();
+    }
+
+    // Lets the user change the command line of the selected entry for this
+    // boot only. None if cancelled
+    fn edit(&self) -> Option<Selection> {
+        let entry = self.entries[self.selected];
+        let out = &mut system_table().con_out;
+        out.set_color(Color::LightGray, Color::Black).ok();
+        out.clear_screen().ok();
+
+        out.set_cursor_position(2, 1).ok();
+        out.set_color(Color::White, Color::Black).ok();
+        write!(out, "Command line of \"{}\"", entry.title).ok();
+
+        out.set_color(Color::DarkGray, Color::Black).ok();
+        out.set_cursor_position(2, FIRST_ROW + 2).ok();
+        write!(out, "Enter to boot, Esc to go back. Changes are not saved").ok();
+
+        let cmdline = LineEditor::new(entry.cmdline, 2, FIRST_ROW, screen_columns().saturating_sub(4)).run()?;
+        Some(Selection {
+            entry,
+            edited: Some(cmdline),
+        })
+    }
+
+    fn run(&mut self, timeout: Option<u32>) -> Selection {
+        let bs = &system_table().boot_services;
+        let con_in = &mut system_table().con_in;
+        let mut ticks = timeout.map(|seconds| seconds.saturating_mul(TICKS_PER_SECOND));
+
+        self.draw();
+        self.draw_countdown(timeout);
+
+        loop {
+            // Any key stops the countdown
+            let key = match ticks {
+                Some(0) => return Selection::new(self.entries[self.selected]),
+                Some(left) => match con_in.read_key_stroke() {
+                    Err(Status::NotReady) => {
+                        bs.stall(TICK);
+                        ticks = Some(left - 1);
+                        if (left - 1) % TICKS_PER_SECOND == 0 {
+                            self.draw_countdown(Some((left - 1) / TICKS_PER_SECOND));
+                        }
+                        continue;
+                    }
+                    key => {
+                        ticks = None;
+                        self.draw_countdown(None);
+                        key
+                    }
+                },
+                None => con_in.read_key_blocking(),
+            };
+
+            let key = match key {
+                Ok(key) => key,
+                Err(_)  => return Selection::new(self.entries[self.selected])
+            };
+            match (key.scan_code, key.unicode_char) {
+                (SCAN_UP, _) if self.selected > 0 => self.select(self.selected - 1),
+                (SCAN_DOWN, _) if self.selected + 1 < self.entries.len() => self.select(self.selected + 1),
+                (_, 0x0D) => return Selection::new(self.entries[self.selected]),
+                (_, unit) if is_edit_key(unit) => match self.edit() {
+                    Some(entry) => return entry,
+                    None        => self.draw()
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+fn screen_columns() -> usize {
+    let out = &system_table().con_out;
+    out.query_mode(out.mode().mode as usize)
+       .map_or(80, |(columns, _)| columns)
+}
+
+// "e", or Ctrl+E as reported by the basic input protocol
+fn is_edit_key(unit: u16) -> bool {
+    unit == 'e' as u16 || unit == 'E' as u16 || unit == 0x05
+}
+
+// Leaves the console as the menu found it
+fn restore_console() {
+    let out = &mut system_table().con_out;
+    out.set_color(Color::LightGray, Color::Black).ok();
+    out.clear_screen().ok();
+    out.enable_cursor(true).ok();
+}
+
+// Picks the entry to boot, showing the menu if needed
+pub fn select(config: &Config, hotkeys: Hotkeys) -> Selection {
+    let shift = hotkeys.finish();
+    let mut menu = Menu {
+        entries:    config.entries(),
+        selected:   config.default_entry(),
+    };
+
+    let entry = if CTRL_E_PRESSED.load(Ordering::Relaxed) {
+        // The menu comes up if editing is cancelled
+        menu.edit().unwrap_or_else(|| menu.run(None))
+    } else if shift {
+        menu.run(None)
+    } else if config.timeout != 0 {
+        menu.run(Some(config.timeout))
+    } else {
+        return Selection::new(menu.entries[menu.selected]);
+    };
+
+    restore_console();
+    entry
+}
@@ -1,9 +1,10 @@
+use crate::{console, log, serial};
 use efi::system_table;
 use core::fmt;
 
 #[macro_export]
 macro_rules! print {
-    ($($arg:tt)*) => ($crate::println::do_println(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::println::do_print($crate::log::Level::Info, format_args!($($arg)*)));
 }
 
 #[macro_export]
@@ -12,7 +13,33 @@ macro_rules! println {
     ($($arg:tt)*)   => (print!("{}\r\n", format_args!($($arg)*)));
 }
 
-pub fn do_println(args: fmt::Arguments) {
+// Same as println!(), recorded in the log with a higher severity
+#[macro_export]
+macro_rules! warnln {
+    ($($arg:tt)*)   => ($crate::println::do_print($crate::log::Level::Warning,
+                                                  format_args!("{}\r\n", format_args!($($arg)*))));
+}
+
+#[macro_export]
+macro_rules! errorln {
+    ($($arg:tt)*)   => ($crate::println::do_print($crate::log::Level::Error,
+                                                  format_args!("{}\r\n", format_args!($($arg)*))));
+}
+
+// Output goes to the firmware console as long as boot services are
+// available and to the framebuffer console once it's attached, which is
+// all that's left after ExitBootServices(). The firmware console is
+// skipped when it draws on the framebuffer console's display. Output is
+// always recorded in the log and mirrored to the serial port, if one is
+// configured
+pub fn do_print(level: log::Level, args: fmt::Arguments) {
     use core::fmt::Write;
-    system_table().con_out.write_fmt(args).unwrap();
+    log::Writer(level).write_fmt(args).ok();
+    serial::Writer.write_fmt(args).ok();
+    if efi::boot_services_active() && !console::replaces_con_out() {
+        system_table().con_out.write_fmt(args).ok();
+    }
+    if console::is_attached() {
+        console::Writer.write_fmt(args).ok();
+    }
 }
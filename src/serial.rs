@@ -0,0 +1,96 @@
+use crate::cpu::{inb, outb};
+use core::fmt;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+// 16550-compatible UART mirroring the loader's output, set up from the
+// "serial" and "serial_baud" config keys. Only port I/O is involved, so
+// it keeps working after ExitBootServices()
+
+const REG_DATA: u16 = 0;
+const REG_IER: u16 = 1;
+const REG_FCR: u16 = 2;
+const REG_LCR: u16 = 3;
+const REG_MCR: u16 = 4;
+const REG_LSR: u16 = 5;
+const REG_SCRATCH: u16 = 7;
+// With LCR.DLAB set
+const REG_DLL: u16 = 0;
+const REG_DLM: u16 = 1;
+
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+// Enable and clear both FIFOs
+const FCR_ENABLE: u8 = 0x07;
+const MCR_DTR_RTS: u8 = 0x03;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+const BASE_BAUD: u32 = 115200;
+// Polls of LSR before a byte is dropped, so a stuck UART can't hang the loader
+const TX_TIMEOUT: usize = 100000;
+
+pub const DEFAULT_BAUD: u32 = 115200;
+
+// Base I/O port, 0 until init() succeeds
+static PORT: AtomicU16 = AtomicU16::new(0);
+
+// Legacy names of the standard ports
+pub fn port_by_name(name: &str) -> Option<u16> {
+    match name {
+        "com1" | "COM1" => Some(0x3F8),
+        "com2" | "COM2" => Some(0x2F8),
+        "com3" | "COM3" => Some(0x3E8),
+        "com4" | "COM4" => Some(0x2E8),
+        _               => None
+    }
+}
+
+pub fn is_valid_baud(baud: u32) -> bool {
+    baud != 0 && BASE_BAUD % baud == 0
+}
+
+// Programs the UART for 8N1 at `baud`, returns false if nothing responds at `port`
+pub fn init(port: u16, baud: u32) -> bool {
+    let divisor = (BASE_BAUD / baud) as u16;
+
+    unsafe {
+        outb(port + REG_SCRATCH, 0x5A);
+        if inb(port + REG_SCRATCH) != 0x5A {
+            return false;
+        }
+
+        outb(port + REG_IER, 0);
+        outb(port + REG_LCR, LCR_DLAB);
+        outb(port + REG_DLL, divisor as u8);
+        outb(port + REG_DLM, (divisor >> 8) as u8);
+        outb(port + REG_LCR, LCR_8N1);
+        outb(port + REG_FCR, FCR_ENABLE);
+        outb(port + REG_MCR, MCR_DTR_RTS);
+    }
+
+    PORT.store(port, Ordering::Relaxed);
+    true
+}
+
+fn write_byte(port: u16, byte: u8) {
+    unsafe {
+        for _ in 0..TX_TIMEOUT {
+            if inb(port + REG_LSR) & LSR_THR_EMPTY != 0 {
+                outb(port + REG_DATA, byte);
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let port = PORT.load(Ordering::Relaxed);
+        if port != 0 {
+            s.bytes().for_each(|byte| write_byte(port, byte));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+use crate::cpu;
+use crate::entry::{self, Trampoline};
+use crate::error::BootError;
+use crate::handoff::{Handoff, HANDOFF_MEMORY_TYPE};
+use crate::mem::PageTables;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use efi::{boot::AllocateType, BootServices, MemoryType, MpServicesProtocol};
+
+// Application processor startup code. The firmware puts the APs back into
+// its own idle loop (or INIT state) in ExitBootServices(), so they are
+// started again with INIT-SIPI-SIPI afterwards. The code is copied to a
+// page below 1MiB (SIPI vector), switches to long mode with the kernel's
+// page tables and GDT and spins on the CPU's table entry until the kernel
+// writes an entry point there. APs are started one at a time, the BSP
+// passes each one its parameters in the page before sending the SIPI.
+//
+// Real mode is entered with CS:IP = page:0000, the page base is then
+// loaded from the parameters and used as a base for the rest of the code
+global_asm!(r#"
+    .section .text
+    .set .Lap_gdtr,     0x800
+    .set .Lap_base,     0x808
+    .set .Lap_cr3,      0x80C
+    .set .Lap_cr4,      0x810
+    .set .Lap_efer,     0x814
+    .set .Lap_pat,      0x818
+    .set .Lap_cpu,      0x820
+    .set .Lap_stack,    0x828
+
+    .code16
+    .global ap_trampoline_start
+    .global ap_trampoline_end
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    mov %ax, %ss
+    mov $0x1000, %sp
+
+    mov .Lap_base, %esi
+    lgdtl .Lap_gdtr
+
+    mov %cr0, %eax
+    or $1, %eax
+    mov %eax, %cr0
+
+    lea (.Lap_prot - ap_trampoline_start)(%esi), %eax
+    pushl $0x18
+    pushl %eax
+    lretl
+
+    .code32
+.Lap_prot:
+    mov $0x10, %eax
+    mov %eax, %ds
+    mov %eax, %es
+    mov %eax, %ss
+    lea 0x1000(%esi), %esp
+
+    mov .Lap_cr4(%esi), %eax
+    mov %eax, %cr4
+    mov .Lap_cr3(%esi), %eax
+    mov %eax, %cr3
+
+    mov $0xC0000080, %ecx
+    mov .Lap_efer(%esi), %eax
+    xor %edx, %edx
+    wrmsr
+
+    // IA32_PAT is per-CPU, match the BSP. Caches are disabled since INIT,
+    // they're switched to no-fill mode (CD = 1, NW = 0) and flushed around
+    // the write as the SDM requires. Paging is still off, so there are
+    // no TLB entries to flush
+    mov .Lap_pat(%esi), %eax
+    mov (.Lap_pat + 4)(%esi), %edx
+    test %eax, %eax
+    jz 1f
+    mov %cr0, %edi
+    and $~0x20000000, %edi
+    or $0x40000000, %edi
+    mov %edi, %cr0
+    wbinvd
+    mov $0x277, %ecx
+    wrmsr
+    wbinvd
+1:
+    // PE | MP | ET | NE | WP | PG, EFER.LME is set. Also enables caching
+    mov $0x80010033, %eax
+    mov %eax, %cr0
+
+    lea (.Lap_long - ap_trampoline_start)(%esi), %eax
+    pushl $0x08
+    pushl %eax
+    lretl
+
+    .code64
+.Lap_long:
+    mov $0x10, %eax
+    mov %eax, %ds
+    mov %eax, %es
+    mov %eax, %fs
+    mov %eax, %gs
+    mov %eax, %ss
+
+    mov %esi, %esi
+    mov .Lap_cpu(%rsi), %rbx
+    mov .Lap_stack(%rsi), %rsp
+
+    fninit
+    pushq $0x1F80
+    ldmxcsr (%rsp)
+    popq %rax
+    pushq $0x2
+    popfq
+
+    // Tell the BSP the parameters were picked up
+    lock orl $0x8, 16(%rbx)
+2:
+    pause
+    mov 24(%rbx), %rax
+    test %rax, %rax
+    jz 2b
+
+    mov 32(%rbx), %rdi
+    xor %rbp, %rbp
+    jmp *%rax
+ap_trampoline_end:
+"#);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+}
+
+pub const CPU_BSP: u32 = 1 << 0;
+pub const CPU_ENABLED: u32 = 1 << 1;
+pub const CPU_HEALTHY: u32 = 1 << 2;
+pub const CPU_PARKED: u32 = 1 << 3;
+
+const AP_PARAMS: usize = 0x800;
+const AP_STACK_SIZE: usize = 0x1000;
+
+const MSR_IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const MSR_X2APIC_ICR: u32 = 0x830;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const ICR_PENDING: u32 = 1 << 12;
+// Level = assert, delivery mode = INIT/Start-up
+const ICR_INIT: u32 = 0x4500;
+const ICR_STARTUP: u32 = 0x4600;
+
+// struct yboot_cpu
+#[repr(C)]
+pub struct CpuEntry {
+    pub apic_id:    u32,
+    pub package:    u32,
+    pub core:       u32,
+    pub thread:     u32,
+    pub flags:      u32,
+    _pad:           u32,
+    pub entry:      u64,
+    pub arg:        u64,
+}
+
+// Must match the offsets in the startup code
+#[repr(C)]
+struct ApParams {
+    gdtr:   [u16; 4],
+    base:   u32,
+    cr3:    u32,
+    cr4:    u32,
+    efer:   u32,
+    pat:    u64,
+    cpu:    u64,
+    stack:  u64,
+}
+
+pub struct Processors {
+    mp:         Option<&'static mut MpServicesProtocol>,
+    count:      usize,
+    park:       bool,
+    low_page:   usize,
+    stacks:     usize,
+    table:      usize,
+}
+
+impl Processors {
+    // Without the MP services protocol only the BSP is reported
+    pub fn query(bs: &BootServices, park: bool) -> Result<Self, BootError> {
+        let mut mp = bs.locate_protocol::<MpServicesProtocol>().ok();
+        let count = match mp {
+            Some(ref mut mp) => mp.get_number_of_processors()
+                .map_err(BootError::ProcessorInfoError)?.0,
+            None => 1
+        };
+
+        let low_page = if park {
+            bs.allocate_pages(AllocateType::MaxAddress,
+                              MemoryType::from(HANDOFF_MEMORY_TYPE),
+                              1,
+                              0x9FFFF)
+                .map_err(BootError::MemoryAllocationError)?
+        } else {
+            0
+        };
+
+        Ok(Processors {
+            mp,
+            count,
+            park,
+            low_page,
+            stacks:     0,
+            table:      0,
+        })
+    }
+
+    // Space needed in the handoff region, including the table's alignment
+    pub fn size(&self) -> usize {
+        let stacks = if self.park { self.count * AP_STACK_SIZE } else { 0 };
+        stacks + self.count * size_of::<CpuEntry>() + 15
+    }
+
+    fn entries(&self) -> &'static mut [CpuEntry] {
+        unsafe { core::slice::from_raw_parts_mut(self.table as *mut CpuEntry, self.count) }
+    }
+
+    // Stacks are allocated first, so this should follow other page-sized
+    // allocations to avoid padding
+    pub fn place(&mut self, handoff: &Handoff) -> Result<(), BootError> {
+        if self.park {
+            self.stacks = handoff.alloc_pages(self.count * AP_STACK_SIZE / 0x1000);
+        }
+        self.table = handoff.alloc(self.count * size_of::<CpuEntry>(), 8);
+
+        let entries = self.entries();
+        match self.mp {
+            Some(ref mut mp) => {
+                for (i, cpu) in entries.iter_mut().enumerate() {
+                    let info = mp.get_processor_info(i).map_err(BootError::ProcessorInfoError)?;
+                    cpu.apic_id = info.processor_id as u32;
+                    cpu.package = info.location.package;
+                    cpu.core = info.location.core;
+                    cpu.thread = info.location.thread;
+                    cpu.flags = info.status_flag & (CPU_BSP | CPU_ENABLED | CPU_HEALTHY);
+                }
+            }
+            None => {
+                entries[0].apic_id = cpu::apic_id();
+                entries[0].flags = CPU_BSP | CPU_ENABLED | CPU_HEALTHY;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns (address, count) of the CPU table
+    pub fn table(&self) -> (usize, usize) {
+        (self.table, self.count)
+    }
+
+    // Starts the enabled APs and waits for each one to reach the spin loop.
+    // Must be called after boot services are terminated, with the page
+    // tables and PAT set up and the TSC calibrated. APs which don't respond
+    // are left in INIT state without CPU_PARKED set
+    pub fn park(&self, tables: &PageTables, trampoline: &Trampoline, pat: u64) {
+        if !self.park {
+            return;
+        }
+
+        unsafe {
+            let start = &ap_trampoline_start as *const u8;
+            let size = &ap_trampoline_end as *const u8 as usize - start as usize;
+            assert!(size <= AP_PARAMS);
+            core::ptr::copy_nonoverlapping(start, self.low_page as *mut u8, size);
+        }
+
+        let params = unsafe { &mut *((self.low_page + AP_PARAMS) as *mut ApParams) };
+        let (gdt, limit) = trampoline.gdt();
+        params.gdtr = [limit, gdt as u16, (gdt >> 16) as u16, 0];
+        params.base = self.low_page as u32;
+        params.cr3 = tables.root() as u32;
+        params.cr4 = entry::kernel_cr4(tables) as u32;
+        params.efer = entry::kernel_efer() as u32;
+        params.pat = pat;
+
+        let vector = (self.low_page >> 12) as u32;
+        let required = CPU_ENABLED | CPU_HEALTHY;
+
+        for (i, cpu) in self.entries().iter_mut().enumerate() {
+            if (cpu.flags & CPU_BSP) != 0 || (cpu.flags & required) != required {
+                continue;
+            }
+
+            unsafe {
+                write_volatile(&mut params.cpu, cpu as *mut CpuEntry as u64);
+                write_volatile(&mut params.stack, (self.stacks + (i + 1) * AP_STACK_SIZE) as u64);
+            }
+            fence(Ordering::SeqCst);
+
+            start_ap(cpu, vector);
+        }
+    }
+}
+
+fn is_parked(cpu: &CpuEntry) -> bool {
+    (unsafe { read_volatile(&cpu.flags) } & CPU_PARKED) != 0
+}
+
+fn wait_parked(cpu: &CpuEntry, us: u64) -> bool {
+    for _ in 0..us / 10 {
+        if is_parked(cpu) {
+            return true;
+        }
+        cpu::delay_us(10);
+    }
+    is_parked(cpu)
+}
+
+fn send_ipi(apic_id: u32, icr: u32) {
+    unsafe {
+        let apic_base = cpu::rdmsr(MSR_IA32_APIC_BASE);
+        if (apic_base & APIC_BASE_X2APIC) != 0 {
+            cpu::wrmsr(MSR_X2APIC_ICR, ((apic_id as u64) << 32) | icr as u64);
+        } else {
+            let mmio = (apic_base & !0xFFF) as usize;
+            write_volatile((mmio + LAPIC_ICR_HIGH) as *mut u32, apic_id << 24);
+            write_volatile((mmio + LAPIC_ICR_LOW) as *mut u32, icr);
+            while (read_volatile((mmio + LAPIC_ICR_LOW) as *const u32) & ICR_PENDING) != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+// INIT-SIPI-SIPI with the usual delays, the second SIPI is only sent
+// if the AP hasn't started after the first one
+fn start_ap(cpu: &mut CpuEntry, vector: u32) {
+    send_ipi(cpu.apic_id, ICR_INIT);
+    cpu::delay_us(10000);
+
+    for _ in 0..2 {
+        send_ipi(cpu.apic_id, ICR_STARTUP | vector);
+        if wait_parked(cpu, 200) {
+            return;
+        }
+    }
+
+    if wait_parked(cpu, 100000) {
+        return;
+    }
+
+    // Put it back into INIT state, so it can't wake up later and pick up
+    // the parameters meant for the next AP, or run from the startup page
+    // after the kernel reclaims it. It may have made it right before that
+    send_ipi(cpu.apic_id, ICR_INIT);
+    cpu::delay_us(10000);
+    unsafe {
+        write_volatile(&mut cpu.flags, read_volatile(&cpu.flags) & !CPU_PARKED);
+    }
+}
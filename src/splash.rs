@@ -0,0 +1,239 @@
+use crate::error::BootError;
+use core::mem::{size_of, MaybeUninit};
+use efi::{gop::BltPixel, BootServices, CStr16, File, GraphicsOutputProtocol, MemoryType};
+
+// Boot splash image, loaded from the ESP (config key "splash") and drawn
+// centered via GOP Blt() while the loader runs. Uncompressed 24/32-bit
+// BMP and QOI files are supported, alpha is blended against black
+
+const MAX_DIMENSION: usize = 8192;
+
+pub struct Splash {
+    pixels: &'static [BltPixel],
+    width:  usize,
+    height: usize,
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn pixel(red: u8, green: u8, blue: u8, alpha: u8) -> BltPixel {
+    let blend = |c: u8| (c as u16 * alpha as u16 / 255) as u8;
+    BltPixel {
+        red:        blend(red),
+        green:      blend(green),
+        blue:       blend(blue),
+        reserved:   0,
+    }
+}
+
+// Extracts the channel selected by `mask`, scaled to 8 bits
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0xFF;
+    }
+    let bits = (mask >> mask.trailing_zeros()).count_ones();
+    let raw = (value & mask) >> mask.trailing_zeros();
+    if bits >= 8 {
+        (raw >> (bits - 8)) as u8
+    } else {
+        (raw * 255 / ((1 << bits) - 1)) as u8
+    }
+}
+
+fn image_size(width: usize, height: usize) -> Option<usize> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return None;
+    }
+    Some(width * height)
+}
+
+// BITMAPINFOHEADER or later, BI_RGB with 24/32 bpp or BI_BITFIELDS with 32 bpp
+fn decode_bmp(data: &[u8], out: &mut dyn FnMut(usize, usize) -> Option<&'static mut [BltPixel]>)
+    -> Option<(&'static mut [BltPixel], usize, usize)> {
+    let offset = read_u32_le(data, 10)? as usize;
+    let width = read_u32_le(data, 18)? as i32;
+    let height = read_u32_le(data, 22)? as i32;
+    let bpp = read_u16_le(data, 28)?;
+    let compression = read_u32_le(data, 30)?;
+
+    let masks = match (compression, bpp) {
+        (0, 24) | (0, 32) => [0x00FF0000, 0x0000FF00, 0x000000FF, 0],
+        // Masks follow the 40-byte header, or are part of a V4/V5 one
+        (3, 32) => [
+            read_u32_le(data, 54)?,
+            read_u32_le(data, 58)?,
+            read_u32_le(data, 62)?,
+            if read_u32_le(data, 14)? >= 56 { read_u32_le(data, 66)? } else { 0 },
+        ],
+        _ => return None,
+    };
+
+    if width <= 0 || height == 0 {
+        return None;
+    }
+    let width = width as usize;
+    // Negative height means rows are stored top-down
+    let top_down = height < 0;
+    let height = (height as i64).abs() as usize;
+
+    let pixels = out(width, height)?;
+    let stride = (bpp as usize * width + 31) / 32 * 4;
+    let bytes = bpp as usize / 8;
+
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let line = data.get(offset + row * stride..offset + row * stride + width * bytes)?;
+
+        for x in 0..width {
+            let px = &line[x * bytes..(x + 1) * bytes];
+            let value = if bytes == 4 {
+                u32::from_le_bytes([px[0], px[1], px[2], px[3]])
+            } else {
+                u32::from_le_bytes([px[0], px[1], px[2], 0])
+            };
+            pixels[y * width + x] = pixel(channel(value, masks[0]),
+                                          channel(value, masks[1]),
+                                          channel(value, masks[2]),
+                                          channel(value, masks[3]));
+        }
+    }
+
+    Some((pixels, width, height))
+}
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xC0;
+const QOI_OP_RGB: u8 = 0xFE;
+const QOI_OP_RGBA: u8 = 0xFF;
+const QOI_MASK_2: u8 = 0xC0;
+
+fn decode_qoi(data: &[u8], out: &mut dyn FnMut(usize, usize) -> Option<&'static mut [BltPixel]>)
+    -> Option<(&'static mut [BltPixel], usize, usize)> {
+    let width = read_u32_be(data, 4)? as usize;
+    let height = read_u32_be(data, 8)? as usize;
+    let count = image_size(width, height)?;
+    let pixels = out(width, height)?;
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let mut pos = 14;
+    let mut run = 0;
+
+    for slot in pixels.iter_mut().take(count) {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let op = *data.get(pos)?;
+            pos += 1;
+
+            if op == QOI_OP_RGB {
+                px[..3].copy_from_slice(data.get(pos..pos + 3)?);
+                pos += 3;
+            } else if op == QOI_OP_RGBA {
+                px.copy_from_slice(data.get(pos..pos + 4)?);
+                pos += 4;
+            } else {
+                match op & QOI_MASK_2 {
+                    QOI_OP_INDEX => px = index[op as usize],
+                    QOI_OP_DIFF => {
+                        px[0] = px[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                        px[1] = px[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                        px[2] = px[2].wrapping_add(op & 3).wrapping_sub(2);
+                    }
+                    QOI_OP_LUMA => {
+                        let next = *data.get(pos)?;
+                        pos += 1;
+                        let dg = (op & 0x3F).wrapping_sub(32);
+                        px[0] = px[0].wrapping_add(dg.wrapping_sub(8).wrapping_add(next >> 4));
+                        px[1] = px[1].wrapping_add(dg);
+                        px[2] = px[2].wrapping_add(dg.wrapping_sub(8).wrapping_add(next & 0xF));
+                    }
+                    _ /* QOI_OP_RUN */ => run = (op & 0x3F) as usize,
+                }
+            }
+
+            let hash = (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64;
+            index[hash] = px;
+        }
+
+        *slot = pixel(px[0], px[1], px[2], px[3]);
+    }
+
+    Some((pixels, width, height))
+}
+
+impl Splash {
+    pub fn load(bs: &BootServices, root: &mut File, path: &CStr16) -> Result<Self, BootError> {
+        let mut statbuf: [u8; 1024] = unsafe { MaybeUninit::uninit().assume_init() };
+        let mut file = root
+            .open(path, efi::proto::fp::OPEN_MODE_READ, 0)
+            .map_err(BootError::FileError)?;
+        let size = file.stat(&mut statbuf).map_err(BootError::FileError)?.file_size as usize;
+
+        let buffer = bs
+            .allocate_pool(MemoryType::LoaderData, size)
+            .map_err(BootError::MemoryAllocationError)?;
+        let data = unsafe { core::slice::from_raw_parts_mut(buffer, size) };
+
+        let result = file.read(data).map_err(BootError::FileError).and_then(|_| {
+            let mut alloc = |width: usize, height: usize| {
+                let size = image_size(width, height)? * size_of::<BltPixel>();
+                let pixels = bs.allocate_pool(MemoryType::LoaderData, size).ok()?;
+                Some(unsafe {
+                    core::slice::from_raw_parts_mut(pixels as *mut BltPixel, width * height)
+                })
+            };
+
+            let image = if data.starts_with(b"BM") {
+                decode_bmp(data, &mut alloc)
+            } else if data.starts_with(b"qoif") {
+                decode_qoi(data, &mut alloc)
+            } else {
+                None
+            };
+
+            image.map(|(pixels, width, height)| Splash { pixels, width, height })
+                 .ok_or(BootError::BadSplashImage)
+        });
+
+        bs.free_pool(buffer).ok();
+        result
+    }
+
+    // Draws the image centered on the current mode, cropping it if the
+    // screen is smaller. Does nothing without a framebuffer mode
+    pub fn draw(&self, gop: &mut GraphicsOutputProtocol) {
+        let (screen_width, screen_height) = match gop.current_mode().info() {
+            Some(info) => (info.horizontal_resolution as usize, info.vertical_resolution as usize),
+            None       => return
+        };
+
+        let width = core::cmp::min(self.width, screen_width);
+        let height = core::cmp::min(self.height, screen_height);
+
+        gop.blt_fill(BltPixel::default(), 0, 0, screen_width, screen_height).ok();
+        gop.blt_buffer_to_video(self.pixels,
+                                self.width,
+                                (self.width - width) / 2,
+                                (self.height - height) / 2,
+                                (screen_width - width) / 2,
+                                (screen_height - height) / 2,
+                                width,
+                                height).ok();
+    }
+}
@@ -1,64 +1,328 @@
-use crate::error::BootError;
-use efi::{gop::ModeInformation, BootServices, GraphicsOutputProtocol};
-use yboot2_proto::{video::PixelFormat, LoadProtocol, VideoInfo};
+use crate::{config::Config, error::BootError, font};
+use core::{fmt, ptr::null_mut};
+use efi::{
+    boot::LocateSearchType,
+    gop::{Mode, ModeInformation, PixelBitmask},
+    system_table, BootServices, DevicePathProtocol, DevicePathToTextProtocol, EdidActiveProtocol,
+    EdidDiscoveredProtocol, GraphicsOutputProtocol, Handle,
+};
+use yboot2_proto::{
+    video::{ModeSelect, PixelFormat},
+    LoadProtocol, VideoInfo,
+};
 
-// TODO: "Any" format
-// TODO: "Text" format
-
-fn pixel_to_efi(from: PixelFormat) -> Option<efi::gop::PixelFormat> {
-    use efi::gop::PixelFormat::*;
-    match from {
-        PixelFormat::LfbRgb32 => Some(PixelRedGreenBlueReserved8BitPerColor),
-        PixelFormat::LfbBgr32 => Some(PixelBlueGreenRedReserved8BitPerColor),
-        _ => None,
-    }
-}
+// Character cell of the loader's framebuffer console, used to report the
+// text geometry when there's no real text mode
+pub const CELL_WIDTH: u32 = font::WIDTH;
+pub const CELL_HEIGHT: u32 = font::HEIGHT;
 
+// Blt-only modes have no framebuffer to hand over
 fn pixel_from_efi(from: efi::gop::PixelFormat) -> Option<PixelFormat> {
     use efi::gop::PixelFormat::*;
     match from {
         PixelRedGreenBlueReserved8BitPerColor => Some(PixelFormat::LfbRgb32),
         PixelBlueGreenRedReserved8BitPerColor => Some(PixelFormat::LfbBgr32),
+        PixelBitMask => Some(PixelFormat::LfbBitmask),
+        PixelBltOnly => None,
+    }
+}
+
+fn format_matches(info: &ModeInformation, req: PixelFormat) -> bool {
+    match req {
+        PixelFormat::Any => info.bits_per_pixel() == Some(32),
+        _                => pixel_from_efi(info.pixel_format) == Some(req)
+    }
+}
+
+fn distance(info: &ModeInformation, width: u32, height: u32) -> u64 {
+    let dx = (info.horizontal_resolution as i64 - width as i64).abs();
+    let dy = (info.vertical_resolution as i64 - height as i64).abs();
+    (dx + dy) as u64
+}
+
+fn area(info: &ModeInformation) -> u64 {
+    info.horizontal_resolution as u64 * info.vertical_resolution as u64
+}
+
+// Lower is better, None if the mode doesn't fit the request at all.
+// Ties are resolved in favor of the larger mode
+fn score(info: &ModeInformation, req: &VideoInfo, select: ModeSelect, native: (u32, u32)) -> Option<u64> {
+    // Only modes with a framebuffer the kernel can be told about
+    info.bits_per_pixel()?;
+    if !format_matches(info, req.format) {
+        return None;
+    }
+
+    match select {
+        ModeSelect::Exact => {
+            if info.horizontal_resolution == req.width && info.vertical_resolution == req.height {
+                Some(0)
+            } else {
+                None
+            }
+        }
+        ModeSelect::Native  => Some(distance(info, native.0, native.1)),
+        ModeSelect::Largest => Some(0),
+        ModeSelect::Closest => Some(distance(info, req.width, req.height)),
+    }
+}
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+// Active resolution of the first detailed timing descriptor, which is
+// the display's preferred (native) timing
+fn preferred_resolution(edid: &[u8]) -> Option<(u32, u32)> {
+    if edid.len() < 128 || edid[..8] != EDID_HEADER {
+        return None;
+    }
+
+    let dtd = &edid[54..72];
+    // Zero pixel clock means a display descriptor instead of a timing
+    if dtd[0] == 0 && dtd[1] == 0 {
+        return None;
+    }
+    let width = dtd[2] as u32 | ((dtd[4] as u32 & 0xF0) << 4);
+    let height = dtd[5] as u32 | ((dtd[7] as u32 & 0xF0) << 4);
+    Some((width, height))
+}
+
+fn handle_edid(bs: &BootServices, handle: Handle) -> Option<&'static [u8]> {
+    bs.handle_protocol::<EdidActiveProtocol>(handle)
+        .ok()
+        .and_then(|proto| proto.edid())
+        .or_else(|| bs.handle_protocol::<EdidDiscoveredProtocol>(handle).ok()?.edid())
+}
+
+const PATH_TEXT_SIZE: usize = 256;
+
+// Device path text of an output, copied out of the firmware's pool string.
+// Longer paths are truncated
+pub struct PathText {
+    buf:    [u8; PATH_TEXT_SIZE],
+    len:    usize,
+}
+
+impl PathText {
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for PathText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len + c.len_utf8() > PATH_TEXT_SIZE {
+                break;
+            }
+            self.len += c.encode_utf8(&mut self.buf[self.len..]).len();
+        }
+        Ok(())
+    }
+}
+
+fn handle_path(bs: &BootServices, handle: Handle) -> Option<PathText> {
+    use core::fmt::Write;
+
+    let path = bs.handle_protocol::<DevicePathProtocol>(handle).ok()?;
+    let text = bs.locate_protocol::<DevicePathToTextProtocol>().ok()?.path_to_text(path)?;
+
+    let mut copy = PathText {
+        buf:    [0; PATH_TEXT_SIZE],
+        len:    0,
+    };
+    write!(copy, "{}", text).ok();
+    bs.free_pool(text.as_ptr() as *mut u8).ok();
+    Some(copy)
+}
+
+// A GOP instance with what identifies the display behind it. `number` is
+// None for the firmware's default instance
+pub struct Output {
+    pub gop:    &'static mut GraphicsOutputProtocol,
+    pub number: Option<usize>,
+    pub path:   Option<PathText>,
+    pub edid:   Option<&'static [u8]>,
+}
+
+impl Output {
+    fn new(bs: &BootServices, handle: Handle) -> Option<Self> {
+        Some(Output {
+            gop:    bs.handle_protocol::<GraphicsOutputProtocol>(handle).ok()?,
+            number: None,
+            path:   handle_path(bs, handle),
+            edid:   handle_edid(bs, handle),
+        })
+    }
+
+    // `name` is either the output's number or the start of its device path
+    fn matches(&self, number: usize, name: &str) -> bool {
+        match name.parse::<usize>() {
+            Ok(n)  => n == number,
+            Err(_) => self.path.as_ref().map_or(false, |path| path.as_str().starts_with(name))
+        }
+    }
+}
+
+// Lists the physical outputs (GOP instances with a device path) and picks
+// the one named by "video_output" in the config, or else the first one
+// with a display attached (that has an EDID). If neither works out, the
+// firmware's default instance is used, which may be a virtual one
+// mirroring the console to every display
+pub fn select_output(bs: &BootServices, config: &Config) -> Option<Output> {
+    let handles = bs
+        .handle_buffer_iter::<GraphicsOutputProtocol>(LocateSearchType::ByProtocol, null_mut())
+        .ok()?;
+
+    let mut configured = None;
+    let mut connected = None;
+    let physical = handles
+        .filter_map(|handle| Some((handle, Output::new(bs, handle)?)))
+        .filter(|(_, output)| output.path.is_some());
+
+    for (number, (handle, output)) in physical.enumerate() {
+        print!("Display {}: {}", number, output.path.as_ref().unwrap().as_str());
+        if let Some(info) = output.gop.current_mode().info() {
+            print!(", {}x{}", info.horizontal_resolution, info.vertical_resolution);
+        }
+        println!("{}", if output.edid.is_some() { ", connected" } else { "" });
+
+        if configured.is_none() && config.video_output.map_or(false, |name| output.matches(number, name)) {
+            configured = Some((number, handle));
+        }
+        if connected.is_none() && output.edid.is_some() {
+            connected = Some((number, handle));
+        }
+    }
+
+    if let (Some(name), None) = (config.video_output, configured) {
+        warnln!("yboot.cfg: no display matches video_output \"{}\"", name);
+    }
+
+    match configured.or(connected) {
+        Some((number, handle)) => {
+            println!("Using display {}", number);
+            let output = Output::new(bs, handle)?;
+            Some(Output { number: Some(number), ..output })
+        }
+        None => Some(Output {
+            gop:    bs.locate_protocol::<GraphicsOutputProtocol>().ok()?,
+            number: None,
+            path:   None,
+            edid:   None,
+        }),
     }
 }
 
 fn find_mode(
     proto: &GraphicsOutputProtocol,
     req: &VideoInfo,
+    select: ModeSelect,
+    edid: Option<&[u8]>,
 ) -> Result<(u32, &'static ModeInformation), BootError> {
-    let req_format = pixel_to_efi(req.format).unwrap();
+    // Without EDID, the mode the firmware set up is assumed to be the
+    // display's native one
+    let native = edid.and_then(preferred_resolution).unwrap_or_else(|| {
+        proto
+            .current_mode()
+            .info()
+            .map_or((0, 0), |info| (info.horizontal_resolution, info.vertical_resolution))
+    });
+
+    let mut best: Option<(u64, u32, &'static ModeInformation)> = None;
     for (num, info) in proto.mode_iter() {
-        if info.horizontal_resolution == req.width
-            && info.vertical_resolution == req.height
-            && info.pixel_format == req_format
-        {
-            return Ok((num, info));
+        let score = match score(info, req, select, native) {
+            Some(score) => score,
+            None        => continue
+        };
+
+        let better = match best {
+            None            => true,
+            Some((s, _, b)) => score < s || (score == s && area(info) > area(b))
+        };
+        if better {
+            best = Some((score, num, info));
         }
     }
-    Err(BootError::VideoModeUnsupported)
+
+    best.map(|(_, num, info)| (num, info)).ok_or(BootError::VideoModeUnsupported)
 }
 
-pub fn set_mode<T: LoadProtocol>(bs: &BootServices, data: &mut T) -> Result<(), BootError> {
-    let gop = bs
-        .locate_protocol::<GraphicsOutputProtocol>()
-        .map_err(|_| BootError::VideoModeFailed)?;
+fn video_info(info: &ModeInformation, mode: &Mode) -> Option<VideoInfo> {
+    Some(VideoInfo {
+        width: info.horizontal_resolution,
+        height: info.vertical_resolution,
+        format: pixel_from_efi(info.pixel_format)?,
+        framebuffer: mode.framebuffer_addr() as u64,
+        pitch: info.pitch()? as u64,
+    })
+}
+
+// Reports the mode to the kernel, fails for modes without a framebuffer
+fn report_mode<T: LoadProtocol>(
+    data: &mut T,
+    info: &ModeInformation,
+    mode: &Mode,
+) -> Option<(VideoInfo, PixelBitmask)> {
+    let video = video_info(info, mode)?;
+    let mask = info.bitmask()?;
+
+    data.set_video_info(&video);
+    data.set_video_bitmask(mask.red_mask, mask.green_mask, mask.blue_mask, mask.reserved_mask);
+    Some((video, mask))
+}
+
+// Returns the framebuffer to attach the console to, which is left to the
+// caller so anything drawn after the mode switch doesn't wipe its output
+pub fn set_mode<T: LoadProtocol>(
+    output: Option<&mut Output>,
+    data: &mut T,
+) -> Result<Option<(VideoInfo, PixelBitmask)>, BootError> {
+    let output = output.ok_or(BootError::VideoModeFailed)?;
 
-    match find_mode(gop, data.get_video_info()) {
-        Ok((num, info)) => {
-            let mode = gop.set_mode(num).map_err(|_| BootError::VideoModeFailed)?;
+    let request = data.get_video_info().map_err(|_| BootError::VideoModeUnsupported)?;
+    let (num, info) = find_mode(output.gop, &request, data.get_video_select(), output.edid)?;
+    let mode = output.gop.set_mode(num).map_err(|_| BootError::VideoModeFailed)?;
 
-            let info = VideoInfo {
-                width: info.horizontal_resolution,
-                height: info.vertical_resolution,
-                format: pixel_from_efi(info.pixel_format).unwrap(),
-                framebuffer: mode.framebuffer_addr() as u64,
-                pitch: 4 * info.horizontal_resolution as u64,
-            };
+    let (video, mask) = report_mode(data, info, mode).ok_or(BootError::VideoModeUnsupported)?;
+    data.set_video_text(0, 0);
 
-            data.set_video_info(&info);
+    Ok(Some((video, mask)))
+}
+
+// Leaves the display as it is. Without GOP the console is in a real text
+// mode and only its geometry is reported. Otherwise the firmware console
+// is drawn on the framebuffer, which is reported (in its current mode)
+// along with the cell grid of the loader's framebuffer console, which is
+// returned to be attached like for set_mode()
+pub fn set_text<T: LoadProtocol>(
+    output: Option<&mut Output>,
+    data: &mut T,
+) -> Result<Option<(VideoInfo, PixelBitmask)>, BootError> {
+    let framebuffer = output.and_then(|output| {
+        let mode = output.gop.current_mode();
+        report_mode(data, mode.info()?, mode)
+    });
+
+    match framebuffer {
+        Some((video, mask)) => {
+            data.set_video_text(video.width / CELL_WIDTH, video.height / CELL_HEIGHT);
+            Ok(Some((video, mask)))
+        }
+        None => {
+            let con_out = &system_table().con_out;
+            let (columns, rows) = con_out
+                .query_mode(con_out.mode().mode as usize)
+                .map_err(|_| BootError::VideoModeFailed)?;
 
-            Ok(())
+            data.set_video_info(&VideoInfo {
+                width: 0,
+                height: 0,
+                format: PixelFormat::Text,
+                framebuffer: 0,
+                pitch: 0,
+            });
+            data.set_video_text(columns as u32, rows as u32);
+            Ok(None)
         }
-        Err(err) => Err(err),
     }
 }
@@ -0,0 +1,125 @@
+#!/usr/bin/env python3
+# Fills the loader's embedded symbol table (src/backtrace.rs) after linking.
+# Usage: embed-symbols.py LINKER_MAP EFI_IMAGE
+# Function symbols are taken from the lld-link /map output, demangled and
+# written into the "YBSYMTAB" area of the image's .ysyms section, so panic
+# backtraces can be symbolised at runtime
+import re
+import struct
+import sys
+
+MAGIC = b"YBSYMTAB"
+HEADER_SIZE = 16
+SECTION = b".ysyms"
+
+SYMBOL_LINE = re.compile(r"^\s*([0-9a-fA-F]{4}):([0-9a-fA-F]{8})\s+(\S+)\s+([0-9a-fA-F]{16})\b")
+SECTION_LINE = re.compile(r"^\s*([0-9a-fA-F]{4}):[0-9a-fA-F]{8}\s+[0-9a-fA-F]+H\s+\S+\s+(\w+)")
+BASE_LINE = re.compile(r"Preferred load address is ([0-9a-fA-F]+)")
+
+ESCAPES = {
+    "$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">",
+    "$LP$": "(", "$RP$": ")", "$C$": ",",
+    "$u20$": " ", "$u27$": "'", "$u5b$": "[", "$u5d$": "]",
+    "$u7b$": "{", "$u7d$": "}", "$u7e$": "~",
+}
+
+
+def demangle(name):
+    # Legacy Rust mangling: _ZN <len><ident>... [17h<hash>] E
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+    body = name[3:-1]
+    parts = []
+    while body:
+        m = re.match(r"(\d+)", body)
+        if not m:
+            return name
+        length = int(m.group(1))
+        start = len(m.group(1))
+        parts.append(body[start:start + length])
+        body = body[start + length:]
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+
+    result = []
+    for part in parts:
+        if part.startswith("_$"):
+            part = part[1:]
+        for escape, char in ESCAPES.items():
+            part = part.replace(escape, char)
+        result.append(part.replace("..", "::"))
+    return "::".join(result)
+
+
+def read_map(path):
+    base = None
+    code_sections = set()
+    symbols = {}
+
+    with open(path) as f:
+        for line in f:
+            m = BASE_LINE.search(line)
+            if m:
+                base = int(m.group(1), 16)
+                continue
+            m = SECTION_LINE.match(line)
+            if m and m.group(2) == "CODE":
+                code_sections.add(int(m.group(1), 16))
+                continue
+            m = SYMBOL_LINE.match(line)
+            if m and int(m.group(1), 16) in code_sections:
+                rva = int(m.group(4), 16) - base
+                symbols.setdefault(rva, demangle(m.group(3)))
+
+    return sorted(symbols.items())
+
+
+def find_table(image):
+    pe = struct.unpack_from("<I", image, 0x3C)[0]
+    if image[pe:pe + 4] != b"PE\0\0":
+        sys.exit("not a PE image")
+    sections, = struct.unpack_from("<H", image, pe + 6)
+    optional_size, = struct.unpack_from("<H", image, pe + 20)
+    table = pe + 24 + optional_size
+
+    for i in range(sections):
+        header = table + i * 40
+        name = image[header:header + 8].rstrip(b"\0")
+        virtual_size, virtual_address, raw_size, raw_offset = struct.unpack_from("<IIII", image, header + 8)
+        if name != SECTION:
+            continue
+        end = raw_offset + min(raw_size, virtual_size)
+        offset = image.find(MAGIC, raw_offset, end)
+        if offset < 0:
+            break
+        return offset, virtual_address + offset - raw_offset, end - offset
+    sys.exit("no symbol table area in the image")
+
+
+def main(map_path, image_path):
+    symbols = read_map(map_path)
+    with open(image_path, "rb") as f:
+        image = bytearray(f.read())
+    offset, rva, size = find_table(image)
+
+    entries = bytearray()
+    names = bytearray()
+    names_start = len(symbols) * 8
+    for sym_rva, name in symbols:
+        entries += struct.pack("<II", sym_rva, names_start + len(names))
+        names += name.encode() + b"\0"
+
+    data = struct.pack("<8sII", MAGIC, rva, len(symbols)) + entries + names
+    if len(data) > size:
+        sys.exit("symbol table doesn't fit: %d bytes, %d available" % (len(data), size))
+    image[offset:offset + len(data)] = data
+
+    with open(image_path, "wb") as f:
+        f.write(image)
+    print("%d symbols, %d of %d bytes" % (len(symbols), len(data), size))
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 3:
+        sys.exit("usage: %s LINKER_MAP EFI_IMAGE" % sys.argv[0])
+    main(sys.argv[1], sys.argv[2])
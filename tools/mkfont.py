@@ -0,0 +1,1161 @@
+#!/usr/bin/env python3
+# Generates src/font.psf, the 8x16 PSF2 font of the framebuffer console.
+# Glyphs are drawn below as 13 rows (screen rows 2..14) of up to 8 columns,
+# "#" - set, anything else - clear. Capitals and digits take rows 0..9,
+# descenders go down to row 12. Glyph 0 is the fallback for characters
+# not in the font
+import struct
+import sys
+
+GLYPHS = {
+0x00: """
+#######
+#.....#
+#.....#
+#.....#
+#.....#
+#.....#
+#.....#
+#.....#
+#.....#
+#######
+""",
+' ': "",
+'!': """
+...#
+..###
+..###
+..###
+...#
+...#
+...#
+.
+...#
+...#
+""",
+'"': """
+.##..##
+.##..##
+.##..##
+..#..#
+""",
+'#': """
+.
+.##.##
+.##.##
+#######
+.##.##
+.##.##
+.##.##
+#######
+.##.##
+.##.##
+""",
+'$': """
+...#
+.#####
+##...##
+##....#
+.#####
+.....##
+#....##
+##...##
+.#####
+...#
+...#
+""",
+'%': """
+.
+.
+##....#
+##...##
+....##
+...##
+..##
+.##
+##...##
+#....##
+""",
+'&': """
+..###
+.##.##
+.##.##
+..###
+.###.##
+##.###
+##..##
+##..##
+##.###
+.###.##
+""",
+"'": """
+..##
+..##
+..##
+.##
+""",
+'(': """
+....##
+...##
+..##
+..##
+..##
+..##
+..##
+..##
+...##
+....##
+""",
+')': """
+.##
+..##
+...##
+...##
+...##
+...##
+...##
+...##
+..##
+.##
+""",
+'*': """
+.
+.
+.
+.##..##
+..####
+#######
+..####
+.##..##
+""",
+'+': """
+.
+.
+.
+...##
+...##
+.######
+...##
+...##
+""",
+',': """
+.
+.
+.
+.
+.
+.
+.
+...##
+...##
+...##
+..##
+""",
+'-': """
+.
+.
+.
+.
+.
+#######
+""",
+'.': """
+.
+.
+.
+.
+.
+.
+.
+.
+...##
+...##
+""",
+'/': """
+.
+.
+......#
+.....##
+....##
+...##
+..##
+.##
+##
+#
+""",
+'0': """
+..###
+.##.##
+##...##
+##..###
+##.#.##
+##.#.##
+###..##
+##...##
+.##.##
+..###
+""",
+'1': """
+...##
+..###
+.####
+...##
+...##
+...##
+...##
+...##
+...##
+.######
+""",
+'2': """
+.#####
+##...##
+.....##
+....##
+...##
+..##
+.##
+##
+##...##
+#######
+""",
+'3': """
+.#####
+##...##
+.....##
+.....##
+..####
+.....##
+.....##
+.....##
+##...##
+.#####
+""",
+'4': """
+....##
+...###
+..####
+.##.##
+##..##
+#######
+....##
+....##
+....##
+...####
+""",
+'5': """
+#######
+##
+##
+##
+######
+.....##
+.....##
+.....##
+##...##
+.#####
+""",
+'6': """
+..###
+.##
+##
+##
+######
+##...##
+##...##
+##...##
+##...##
+.#####
+""",
+'7': """
+#######
+##...##
+.....##
+....##
+...##
+..##
+..##
+..##
+..##
+..##
+""",
+'8': """
+.#####
+##...##
+##...##
+##...##
+.#####
+##...##
+##...##
+##...##
+##...##
+.#####
+""",
+'9': """
+.#####
+##...##
+##...##
+##...##
+.######
+.....##
+.....##
+.....##
+....##
+.####
+""",
+':': """
+.
+.
+...##
+...##
+.
+.
+.
+...##
+...##
+""",
+';': """
+.
+.
+...##
+...##
+.
+.
+.
+...##
+...##
+..##
+""",
+'<': """
+.
+.....##
+....##
+...##
+..##
+.##
+..##
+...##
+....##
+.....##
+""",
+'=': """
+.
+.
+.
+.
+#######
+.
+.
+#######
+""",
+'>': """
+.
+.##
+..##
+...##
+....##
+.....##
+....##
+...##
+..##
+.##
+""",
+'?': """
+.#####
+##...##
+##...##
+....##
+...##
+...##
+...##
+.
+...##
+...##
+""",
+'@': """
+.
+.#####
+##...##
+##...##
+##.####
+##.####
+##.####
+##.###
+##
+.#####
+""",
+'A': """
+...#
+..###
+.##.##
+##...##
+##...##
+#######
+##...##
+##...##
+##...##
+##...##
+""",
+'B': """
+######
+.##..##
+.##..##
+.##..##
+.#####
+.##..##
+.##..##
+.##..##
+.##..##
+######
+""",
+'C': """
+..####
+.##..##
+##....#
+##
+##
+##
+##
+##....#
+.##..##
+..####
+""",
+'D': """
+#####
+.##.##
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##.##
+#####
+""",
+'E': """
+#######
+.##..##
+.##...#
+.##.#
+.####
+.##.#
+.##
+.##...#
+.##..##
+#######
+""",
+'F': """
+#######
+.##..##
+.##...#
+.##.#
+.####
+.##.#
+.##
+.##
+.##
+####
+""",
+'G': """
+..####
+.##..##
+##....#
+##
+##
+##.####
+##...##
+##...##
+.##..##
+..###.#
+""",
+'H': """
+##...##
+##...##
+##...##
+##...##
+#######
+##...##
+##...##
+##...##
+##...##
+##...##
+""",
+'I': """
+..####
+...##
+...##
+...##
+...##
+...##
+...##
+...##
+...##
+..####
+""",
+'J': """
+...####
+....##
+....##
+....##
+....##
+....##
+##..##
+##..##
+##..##
+.####
+""",
+'K': """
+###..##
+.##..##
+.##.##
+.##.##
+.####
+.####
+.##.##
+.##..##
+.##..##
+###..##
+""",
+'L': """
+####
+.##
+.##
+.##
+.##
+.##
+.##
+.##...#
+.##..##
+#######
+""",
+'M': """
+##...##
+###.###
+#######
+#######
+##.#.##
+##...##
+##...##
+##...##
+##...##
+##...##
+""",
+'N': """
+##...##
+###..##
+####.##
+#######
+##.####
+##..###
+##...##
+##...##
+##...##
+##...##
+""",
+'O': """
+.#####
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####
+""",
+'P': """
+######
+.##..##
+.##..##
+.##..##
+.#####
+.##
+.##
+.##
+.##
+####
+""",
+'Q': """
+.#####
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##.#.##
+##.####
+.#####
+....##
+....###
+""",
+'R': """
+######
+.##..##
+.##..##
+.##..##
+.#####
+.##.##
+.##..##
+.##..##
+.##..##
+###..##
+""",
+'S': """
+.#####
+##...##
+##...##
+.##
+..###
+....##
+.....##
+##...##
+##...##
+.#####
+""",
+'T': """
+.######
+.######
+.#.##.#
+...##
+...##
+...##
+...##
+...##
+...##
+..####
+""",
+'U': """
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####
+""",
+'V': """
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.##.##
+..###
+...#
+""",
+'W': """
+##...##
+##...##
+##...##
+##...##
+##.#.##
+##.#.##
+##.#.##
+#######
+.##.##
+.##.##
+""",
+'X': """
+##...##
+##...##
+.##.##
+.#####
+..###
+..###
+.#####
+.##.##
+##...##
+##...##
+""",
+'Y': """
+.##..##
+.##..##
+.##..##
+.##..##
+..####
+...##
+...##
+...##
+...##
+..####
+""",
+'Z': """
+#######
+##...##
+#....##
+....##
+...##
+..##
+.##
+##....#
+##...##
+#######
+""",
+'[': """
+..####
+..##
+..##
+..##
+..##
+..##
+..##
+..##
+..##
+..####
+""",
+'\\': """
+.
+#
+##
+.##
+..##
+...##
+....##
+.....##
+......#
+""",
+']': """
+..####
+....##
+....##
+....##
+....##
+....##
+....##
+....##
+....##
+..####
+""",
+'^': """
+...#
+..###
+.##.##
+##...##
+""",
+'_': """
+.
+.
+.
+.
+.
+.
+.
+.
+.
+.
+.
+########
+""",
+'`': """
+..##
+..##
+...##
+""",
+'a': """
+.
+.
+.
+.####
+....##
+.#####
+##..##
+##..##
+##..##
+.###.##
+""",
+'b': """
+###
+.##
+.##
+.####
+.##.##
+.##..##
+.##..##
+.##..##
+.##..##
+.#####
+""",
+'c': """
+.
+.
+.
+.#####
+##...##
+##
+##
+##
+##...##
+.#####
+""",
+'d': """
+...###
+....##
+....##
+..####
+.##.##
+##..##
+##..##
+##..##
+##..##
+.###.##
+""",
+'e': """
+.
+.
+.
+.#####
+##...##
+#######
+##
+##
+##...##
+.#####
+""",
+'f': """
+..###
+.##.##
+.##..#
+.##
+####
+.##
+.##
+.##
+.##
+####
+""",
+'g': """
+.
+.
+.
+.###.##
+##..##
+##..##
+##..##
+##..##
+##..##
+.#####
+....##
+##..##
+.####
+""",
+'h': """
+###
+.##
+.##
+.##.##
+.###.##
+.##..##
+.##..##
+.##..##
+.##..##
+###..##
+""",
+'i': """
+...##
+...##
+.
+..###
+...##
+...##
+...##
+...##
+...##
+..####
+""",
+'j': """
+.....##
+.....##
+.
+....###
+.....##
+.....##
+.....##
+.....##
+.....##
+.....##
+.##..##
+.##..##
+..####
+""",
+'k': """
+###
+.##
+.##
+.##..##
+.##.##
+.####
+.####
+.##.##
+.##..##
+###..##
+""",
+'l': """
+..###
+...##
+...##
+...##
+...##
+...##
+...##
+...##
+...##
+..####
+""",
+'m': """
+.
+.
+.
+###.##
+#######
+##.#.##
+##.#.##
+##.#.##
+##.#.##
+##...##
+""",
+'n': """
+.
+.
+.
+##.###
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+""",
+'o': """
+.
+.
+.
+.#####
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####
+""",
+'p': """
+.
+.
+.
+##.###
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.#####
+.##
+.##
+####
+""",
+'q': """
+.
+.
+.
+.###.##
+##..##
+##..##
+##..##
+##..##
+##..##
+.#####
+....##
+....##
+...####
+""",
+'r': """
+.
+.
+.
+##.###
+.###.##
+.##..##
+.##
+.##
+.##
+####
+""",
+'s': """
+.
+.
+.
+.#####
+##...##
+.##
+..###
+....##
+##...##
+.#####
+""",
+'t': """
+...#
+..##
+..##
+######
+..##
+..##
+..##
+..##
+..##.##
+...###
+""",
+'u': """
+.
+.
+.
+##..##
+##..##
+##..##
+##..##
+##..##
+##..##
+.###.##
+""",
+'v': """
+.
+.
+.
+##...##
+##...##
+##...##
+##...##
+.##.##
+..###
+...#
+""",
+'w': """
+.
+.
+.
+##...##
+##...##
+##.#.##
+##.#.##
+##.#.##
+#######
+.##.##
+""",
+'x': """
+.
+.
+.
+##...##
+.##.##
+..###
+..###
+..###
+.##.##
+##...##
+""",
+'y': """
+.
+.
+.
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.######
+.....##
+....##
+#####
+""",
+'z': """
+.
+.
+.
+#######
+##..##
+...##
+..##
+.##
+##...##
+#######
+""",
+'{': """
+....###
+...##
+...##
+...##
+.###
+...##
+...##
+...##
+...##
+....###
+""",
+'|': """
+...##
+...##
+...##
+...##
+.
+...##
+...##
+...##
+...##
+...##
+""",
+'}': """
+.###
+...##
+...##
+...##
+....###
+...##
+...##
+...##
+...##
+.###
+""",
+'~': """
+.###.##
+##.###
+""",
+}
+
+WIDTH = 8
+HEIGHT = 16
+TOP = 2
+ROWS = 13
+COUNT = 128
+
+
+def glyph_bytes(art):
+    lines = art.strip("\n").split("\n") if art.strip() else []
+    assert len(lines) <= ROWS, lines
+    data = bytearray(HEIGHT)
+    for y, line in enumerate(lines):
+        assert len(line) <= WIDTH, line
+        data[TOP + y] = sum(0x80 >> x for x, c in enumerate(line) if c == "#")
+    return bytes(data)
+
+
+def main(path):
+    glyphs = [GLYPHS[0x00]] * COUNT
+    for key, art in GLYPHS.items():
+        index = key if isinstance(key, int) else ord(key)
+        glyphs[index] = art
+
+    header = struct.pack("<IIIIIIII", 0x864AB572, 0, 32, 0, COUNT, HEIGHT, HEIGHT, WIDTH)
+    with open(path, "wb") as f:
+        f.write(header)
+        for art in glyphs:
+            f.write(glyph_bytes(art))
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else "src/font.psf")